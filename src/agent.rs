@@ -0,0 +1,94 @@
+use std::time::Duration;
+
+use crate::command::CommandOutput;
+use crate::report::format_duration;
+
+/// The number of steps a `:do` goal may take by default; see `agent.max_steps`
+pub const DEFAULT_MAX_STEPS: usize = 10;
+
+/// What the model replies with once it considers a goal done, followed by a summary
+pub const DONE_MARKER: &str = "DONE";
+
+/// A command run towards a goal, and how it turned out
+#[derive(Clone)]
+pub struct Step {
+  pub command: String,
+  pub output: CommandOutput,
+  pub elapsed: Option<Duration>,
+}
+
+/// What the model proposes next for a goal
+#[derive(Debug, PartialEq)]
+pub enum StepReply {
+  /// A command to run next
+  Command(String),
+  /// The goal is done, or can't be done, with the model's summary of the outcome
+  Done(String),
+}
+
+/// Interprets the model's reply to a step prompt: either `DONE: <summary>`, or the next command. A model with nothing
+/// left to suggest is taken to be done.
+pub fn
+parse_step_reply (reply: &str) -> StepReply
+{
+  let reply = reply.trim().trim_matches('`').trim();
+  match reply.strip_prefix(DONE_MARKER) {
+    Some(summary) if summary.is_empty() || summary.starts_with([':', ' ', '\n', '.']) => {
+      StepReply::Done(summary.trim_start_matches([':', '.']).trim().to_string())
+    },
+    _ if reply.is_empty() => StepReply::Done(String::new()),
+    _ => StepReply::Command(reply.to_string())
+  }
+}
+
+/// A record of the commands run towards a goal: each with its exit status and output, followed by how it ended
+pub fn
+transcript (goal: &str, steps: &[Step], outcome: &str) -> String
+{
+  let mut lines = vec![format!("goal: {goal}")];
+  for (i, step) in steps.iter().enumerate() {
+    let status = match step.elapsed {
+      Some(elapsed) => format!("exit {} · {}", step.output.status_code, format_duration(elapsed)),
+      None => format!("exit {}", step.output.status_code)
+    };
+    lines.push(format!("step {}: {} [{}]", i + 1, step.command, status));
+    lines.extend(step.output.stdout.lines().chain(step.output.stderr.lines()).map(|line| format!("    {line}")));
+  }
+
+  lines.push(format!("outcome: {outcome}"));
+  lines.join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_parse_step_reply () {
+    assert_eq!(parse_step_reply("lsof -ti :8080"), StepReply::Command("lsof -ti :8080".to_string()));
+    assert_eq!(parse_step_reply("`kill 4242`\n"), StepReply::Command("kill 4242".to_string()));
+    assert_eq!(parse_step_reply("DONE: killed process 4242"), StepReply::Done("killed process 4242".to_string()));
+    assert_eq!(parse_step_reply("DONE"), StepReply::Done(String::new()));
+    assert_eq!(parse_step_reply(""), StepReply::Done(String::new()));
+    assert_eq!(parse_step_reply("DONEFILE=x make"), StepReply::Command("DONEFILE=x make".to_string()));
+  }
+
+  #[test]
+  fn test_transcript () {
+    let steps = vec![
+      Step {
+        command: "lsof -ti :8080".to_string(),
+        output: CommandOutput::from_fields(true, 0, "4242\n".to_string(), String::new()),
+        elapsed: Some(Duration::from_millis(31))
+      },
+      Step {
+        command: "kill 4242".to_string(),
+        output: CommandOutput::from_fields(true, 0, String::new(), String::new()),
+        elapsed: None
+      },
+    ];
+
+    assert_eq!(transcript("free port 8080", &steps, "killed process 4242"),
+      "goal: free port 8080\nstep 1: lsof -ti :8080 [exit 0 · 31ms]\n    4242\nstep 2: kill 4242 [exit 0]\noutcome: killed process 4242");
+  }
+}
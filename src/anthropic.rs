@@ -1,5 +1,8 @@
 use clust::Client as AnthropicClient;
 use clust::messages::*;
+use tokio_stream::StreamExt;
+
+use crate::usage::TokenUsage;
 
 pub fn 
 claude_3_haiku () -> ClaudeModel
@@ -20,14 +23,17 @@ claude_3_opus () -> ClaudeModel
 }
 
 pub fn 
-anthropic_client () -> AnthropicClient
+anthropic_client () -> Result<AnthropicClient, Box<dyn std::error::Error>>
 {
   // Expects ANTHROPIC_API_KEY to be set in environment
-  AnthropicClient::from_env().unwrap()
+  AnthropicClient::from_env()
+    .map_err(|e| format!("ANTHROPIC_API_KEY must be set as an environment variable in order to issue requests to Anthropic APIs: {e}").into())
 }
 
+/// Issues a messages request, streaming the response; each fragment of text is passed to `on_text` as it arrives.
+/// Returns the whole response along with the tokens it consumed.
 pub async fn
-issue_anthropic_request (client: &AnthropicClient, model: ClaudeModel, prompt: &str) -> Result<String, Box<dyn std::error::Error>>
+issue_anthropic_request (client: &AnthropicClient, model: ClaudeModel, prompt: &str, on_text: impl Fn(&str)) -> Result<(String, TokenUsage), Box<dyn std::error::Error>>
 {
   let messages = vec![Message::user(prompt)];
   let max_tokens = MaxTokens::default();
@@ -35,14 +41,27 @@ issue_anthropic_request (client: &AnthropicClient, model: ClaudeModel, prompt: &
     model,
     messages,
     max_tokens,
+    stream: Some(StreamOption::ReturnStream),
     ..Default::default()
   };
 
-  let response = client
-    .create_a_message(request_body)
-    .await?;
+  let mut stream = Box::pin(client
+    .create_a_message_stream(request_body)
+    .await?);
 
-  let s = response.content.flatten_into_text()?;
+  let mut text = String::new();
+  let mut usage = TokenUsage::default();
+  while let Some(chunk) = stream.next().await {
+    match chunk? {
+      MessageChunk::MessageStart(start) => usage.input = start.message.usage.input_tokens as u64,
+      MessageChunk::ContentBlockDelta(delta) => {
+        text.push_str(&delta.delta.text);
+        on_text(&delta.delta.text);
+      },
+      MessageChunk::MessageDelta(delta) => usage.output = delta.usage.output_tokens as u64,
+      _ => {}
+    }
+  }
 
-  Ok(s.to_string())
-}
\ No newline at end of file
+  Ok((text, usage))
+}
@@ -1,8 +1,16 @@
+use clust::messages::ClaudeModel;
+
+use crate::anthropic::{claude_3_haiku, claude_3_opus, claude_3_sonnet};
+use crate::config::Config;
+
+#[derive(Clone, Debug, PartialEq)]
 pub enum ModelType {
   GPT4,
   GPT35,
-  Claude,
+  Claude(ClaudeModel),
   Local(String),
+  /// An OpenAI-compatible API at the given URL, serving the named model
+  Endpoint(String, String),
 }
 
 pub struct Args {
@@ -10,31 +18,140 @@ pub struct Args {
   pub model_type: ModelType,
 
   /// Indicates whether any command history should be written to the user's native shell history file
-  pub stateless: bool
+  pub stateless: bool,
+
+  /// Indicates that no data may leave this machine, restricting the choice of model to local ones
+  pub offline: bool
+}
+
+impl ModelType {
+  /// Parses a backend named in configuration or the `:model` builtin: `gpt4`, `gpt35`, `claude` (or specifically
+  /// `claude-haiku`, `claude-sonnet` or `claude-opus`), `local <path>` or `endpoint <url>`; the path or URL may also
+  /// follow a colon, as in `local:<path>`. An endpoint's model is given by the `model.endpoint.model` setting.
+  pub fn parse (spec: &str, config: &Config) -> Option<ModelType>
+  {
+    let spec = spec.trim();
+    let (name, value) = match spec.find(|c: char| c == ':' || c.is_whitespace()) {
+      Some(index) => (&spec[..index], Some(spec[index + 1..].trim().to_string()).filter(|value| !value.is_empty())),
+      None => (spec, None)
+    };
+
+    match (name, value) {
+      ("gpt4", None) => Some(ModelType::GPT4),
+      ("gpt35", None) => Some(ModelType::GPT35),
+      ("claude", None) | ("claude-sonnet", None) => Some(ModelType::Claude(claude_3_sonnet())),
+      ("claude-haiku", None) => Some(ModelType::Claude(claude_3_haiku())),
+      ("claude-opus", None) => Some(ModelType::Claude(claude_3_opus())),
+      ("local", Some(path)) => Some(ModelType::Local(path)),
+      ("endpoint", Some(url)) => {
+        let model = config.get("model.endpoint.model").unwrap_or("default").to_string();
+        Some(ModelType::Endpoint(url, model))
+      },
+      _ => None
+    }
+  }
+
+  /// The backend's name in configuration, as in `model.<key>.timeout`
+  pub fn key (&self) -> &'static str
+  {
+    match self {
+      ModelType::GPT4 => "gpt4",
+      ModelType::GPT35 => "gpt35",
+      ModelType::Claude(_) => "claude",
+      ModelType::Local(_) => "local",
+      ModelType::Endpoint(_, _) => "endpoint",
+    }
+  }
+
+  /// Whether the model runs on this machine, such that prompts never leave it
+  pub fn is_local (&self) -> bool
+  {
+    match self {
+      ModelType::Local(_) => true,
+      ModelType::Endpoint(url, _) => is_local_endpoint(url),
+      _ => false
+    }
+  }
+}
+
+/// Determines whether an API endpoint URL refers to this machine
+pub fn
+is_local_endpoint (url: &str) -> bool
+{
+  let authority = url.split_once("://").map(|(_, rest)| rest).unwrap_or(url);
+  let authority = authority.split('/').next().unwrap_or("");
+  let host = authority.rsplit_once('@').map(|(_, host)| host).unwrap_or(authority);
+
+  // Strip the port, taking care with bracketed IPv6 addresses such as [::1]:8080
+  let host = if let Some(rest) = host.strip_prefix('[') {
+    rest.split(']').next().unwrap_or("")
+  } else {
+    host.split(':').next().unwrap_or("")
+  };
+
+  // Only a literal loopback address will do; a name such as 127.example.com may resolve anywhere
+  host.eq_ignore_ascii_case("localhost") || host.parse::<std::net::IpAddr>().is_ok_and(|ip| ip.is_loopback())
 }
 
 impl Args {
-  pub fn new (matches: &clap::ArgMatches) -> Self 
+  pub fn new (matches: &clap::ArgMatches, config: &Config) -> Self 
   {
     let gpt4 = matches.get_one::<bool>("gpt4").unwrap_or(&true); // Default to true
     let gpt35 = matches.get_one::<bool>("gpt35").unwrap_or(&false);
     let claude = matches.get_one::<bool>("claude").unwrap_or(&false);
     let local_opt = matches.get_one::<String>("local").cloned();
+    let endpoint_opt = matches.get_one::<String>("endpoint").cloned();
 
     let model_type = if let Some(path) = local_opt {
         ModelType::Local(path)
+    } else if let Some(url) = endpoint_opt {
+        let model = config.get("model.endpoint.model").unwrap_or("default").to_string();
+        ModelType::Endpoint(url, model)
     } else if *gpt35 {
         ModelType::GPT35
     } else if *claude {
-        ModelType::Claude
+        ModelType::Claude(claude_3_sonnet())
     } else if *gpt4 {
         ModelType::GPT4
     } else {
         panic!("No model specified");
     };
 
-    let stateless = matches.get_one::<bool>("stateless").map(|&b| b).unwrap_or(false);
+    let stateless = matches.get_one::<bool>("stateless").copied().unwrap_or(false);
+    let offline = matches.get_one::<bool>("offline").copied().unwrap_or(false);
+
+    Args { model_type, stateless, offline }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_parse_model_type () {
+    let config = Config::parse("model.endpoint.model = llama3");
+    assert_eq!(ModelType::parse("claude", &config), Some(ModelType::Claude(claude_3_sonnet())));
+    assert_eq!(ModelType::parse("claude-opus", &config), Some(ModelType::Claude(claude_3_opus())));
+    assert_eq!(ModelType::parse("local ./mistral.gguf", &config), Some(ModelType::Local("./mistral.gguf".to_string())));
+    assert_eq!(ModelType::parse("local:/models/mistral.gguf", &config), Some(ModelType::Local("/models/mistral.gguf".to_string())));
+    assert_eq!(ModelType::parse("endpoint:http://localhost:11434/v1", &config),
+      Some(ModelType::Endpoint("http://localhost:11434/v1".to_string(), "llama3".to_string())));
+    assert_eq!(ModelType::parse("local", &config), None);
+    assert_eq!(ModelType::parse("gpt5", &config), None);
+  }
 
-    Args { model_type, stateless }
+  #[test]
+  fn test_is_local_endpoint () {
+    assert!(is_local_endpoint("http://localhost:8080/v1"));
+    assert!(is_local_endpoint("http://127.0.0.1:11434/v1"));
+    assert!(is_local_endpoint("http://[::1]:8080/v1"));
+    assert!(is_local_endpoint("LOCALHOST"));
+    assert!(!is_local_endpoint("https://api.openai.com/v1"));
+    assert!(!is_local_endpoint("http://localhost.example.com/v1"));
+    assert!(!is_local_endpoint("http://user@evil.com/localhost"));
+    assert!(!is_local_endpoint("http://127.attacker.example/v1"));
+    assert!(!is_local_endpoint("http://127.0.0.1.nip.io:8080/v1"));
+    assert!(is_local_endpoint("http://127.1.2.3/v1"));
   }
 }
@@ -0,0 +1,617 @@
+use std::sync::{Arc, Mutex};
+
+use crate::args::ModelType;
+use crate::command::{CommandExecutorInterface, CommandOutput};
+use crate::context::Context;
+use crate::model::{build_model_chain, wait_for, Model, UsageLedger};
+use crate::providers::ContextProviders;
+
+/// Input beginning with this prefix, followed by a name, runs one of nl-sh's own builtin commands rather than a
+/// system command; as in `:help`
+pub const BUILTIN_PREFIX: char = ':';
+
+/// Settings read when the backend is constructed, which `:config` changes only once the backend is switched
+static BACKEND_SETTINGS: &[&str] = &["model.", "redact."];
+
+/// Settings read only at startup, which `:config` refuses to change
+static STARTUP_SETTINGS: &[&str] = &["classifier.model"];
+
+/// Number of commands shown by `:history` by default
+const DEFAULT_HISTORY_LENGTH: usize = 20;
+
+/// What `:fix` tells the model when the user doesn't say what was wrong
+const DEFAULT_COMPLAINT: &str = "the command ran, but didn't do what I wanted";
+
+/// The shell state available to builtins
+pub struct Session<'a> {
+  pub context: &'a mut Context,
+  pub model: &'a mut Box<dyn Model>,
+  pub executor: &'a dyn CommandExecutorInterface,
+  pub providers: &'a mut ContextProviders,
+  pub usage: &'a Arc<Mutex<UsageLedger>>,
+  /// The most recent command the shell ran, if any
+  pub last_run: &'a Option<LastRun>,
+  /// What the shell does once the builtin has finished, if anything more
+  pub next: &'a mut Option<NextAction>,
+}
+
+/// Work a builtin hands back to the shell, which confirms and runs commands
+#[derive(Debug, PartialEq)]
+pub enum NextAction {
+  /// Propose a command for the request of `last_run`, as `:fix` does
+  Command(String),
+  /// Work towards a goal a step at a time, as `:do` does
+  Goal(String),
+}
+
+/// A command the shell ran, along with the input it was run for and how it turned out
+#[derive(Clone)]
+pub struct LastRun {
+  pub request: String,
+  pub command: String,
+  pub output: CommandOutput,
+}
+
+/// A command implemented by nl-sh itself, run as `:<name> [args]`
+pub trait Builtin {
+  /// The name typed after the prefix
+  fn name (&self) -> &'static str;
+
+  /// A synopsis of the builtin's arguments, if any, for `:help`
+  fn usage (&self) -> &'static str;
+
+  /// A one-line description, for `:help`
+  fn description (&self) -> &'static str;
+
+  /// Runs the builtin with the rest of the input line, returning any output to show the user
+  fn run (&self, args: &str, session: &mut Session) -> Result<String, Box<dyn std::error::Error>>;
+}
+
+/// Lists the builtins; implemented by the registry itself, since it describes all of them
+const HELP: (&str, &str, &str) = ("help", "", "show this list of builtin commands");
+
+/// Shows recently-run commands
+pub struct HistoryBuiltin;
+
+/// Shows or changes configuration settings for the session
+pub struct ConfigBuiltin;
+
+/// Shows the environment details sent to the model
+pub struct ContextBuiltin;
+
+/// Re-probes the environment, replacing any cached facts
+pub struct RefreshContextBuiltin;
+
+/// Shows or switches the model backend
+pub struct ModelBuiltin;
+
+/// Shows token usage and costs
+pub struct UsageBuiltin;
+
+/// Asks the model to revise the last command, which ran but didn't do what the user wanted
+pub struct FixBuiltin;
+
+/// Works towards a goal over several commands, each proposed by the model after seeing the output of the last
+pub struct DoBuiltin;
+
+/// The registry of builtin commands, consulted before input is treated as a system command or sent to the model
+pub struct Builtins {
+  builtins: Vec<Box<dyn Builtin>>,
+}
+
+impl Builtin for HistoryBuiltin
+{
+  fn name (&self) -> &'static str
+  {
+    "history"
+  }
+
+  fn usage (&self) -> &'static str
+  {
+    "[count]"
+  }
+
+  fn description (&self) -> &'static str
+  {
+    "show the most recent commands, oldest first"
+  }
+
+  fn run (&self, args: &str, session: &mut Session) -> Result<String, Box<dyn std::error::Error>>
+  {
+    let count = if args.is_empty() { DEFAULT_HISTORY_LENGTH } else { args.parse()? };
+
+    // History is kept newest first
+    let history = session.context.get_command_history();
+    let shown: Vec<&String> = history.iter().take(count).collect();
+    let first = history.len() - shown.len() + 1;
+
+    Ok(shown.iter().rev().enumerate()
+      .map(|(i, command)| format!("{:>5}  {}", first + i, command))
+      .collect::<Vec<String>>()
+      .join("\n"))
+  }
+}
+
+impl Builtin for ConfigBuiltin
+{
+  fn name (&self) -> &'static str
+  {
+    "config"
+  }
+
+  fn usage (&self) -> &'static str
+  {
+    "[key [value]]"
+  }
+
+  fn description (&self) -> &'static str
+  {
+    "show all settings, one setting, or change a setting for this session"
+  }
+
+  fn run (&self, args: &str, session: &mut Session) -> Result<String, Box<dyn std::error::Error>>
+  {
+    let config = &mut session.context.config;
+    if args.is_empty() {
+      return Ok(config.entries().iter().map(|(key, value)| format!("{key} = {value}")).collect::<Vec<String>>().join("\n"));
+    }
+
+    // Accept both `key value` and `key = value`
+    let (key, value) = match args.split_once(|c: char| c == '=' || c.is_whitespace()) {
+      Some((key, value)) => (key.trim(), value.trim().trim_start_matches('=').trim()),
+      None => (args, "")
+    };
+
+    if value.is_empty() {
+      return Ok(match config.get(key) {
+        Some(value) => format!("{key} = {value}"),
+        None => format!("{key} is not set")
+      });
+    }
+
+    if key == "offline" {
+      return Err("offline mode can only be set at startup".into());
+    }
+
+    if STARTUP_SETTINGS.contains(&key) {
+      return Err(format!("{key} can only be set at startup, in ~/.nl-sh/config").into());
+    }
+
+    // Fallbacks could otherwise send requests off this machine the next time the backend is switched
+    if key == "model.fallback" && config.get_bool("offline", false) {
+      return Err("model.fallback can't be changed in offline mode".into());
+    }
+
+    config.set(key, value);
+    if key.starts_with("context.") {
+      *session.providers = ContextProviders::from_config(config);
+    } else if key.starts_with("usage.") {
+      session.usage.lock().unwrap().reconfigure(config);
+    } else if BACKEND_SETTINGS.iter().any(|prefix| key.starts_with(prefix)) {
+      return Ok(format!("{key} = {value} for this session; takes effect once the backend is switched with {BUILTIN_PREFIX}model"));
+    }
+
+    Ok(format!("{key} = {value} for this session"))
+  }
+}
+
+impl Builtin for ContextBuiltin
+{
+  fn name (&self) -> &'static str
+  {
+    "context"
+  }
+
+  fn usage (&self) -> &'static str
+  {
+    ""
+  }
+
+  fn description (&self) -> &'static str
+  {
+    "show the details of this environment which are sent to the model"
+  }
+
+  fn run (&self, _args: &str, session: &mut Session) -> Result<String, Box<dyn std::error::Error>>
+  {
+    let context = &*session.context;
+    let mut lines = vec![
+      format!("kernel: {}", context.uname),
+      format!("operating system: {}", context.os),
+      format!("shell: {}", context.shell),
+      format!("working directory: {}", context.pwd),
+    ];
+
+    lines.extend(session.providers.gather(context, session.executor));
+    Ok(lines.join("\n"))
+  }
+}
+
+impl Builtin for RefreshContextBuiltin
+{
+  fn name (&self) -> &'static str
+  {
+    "refresh-context"
+  }
+
+  fn usage (&self) -> &'static str
+  {
+    ""
+  }
+
+  fn description (&self) -> &'static str
+  {
+    "re-probe the operating system, replacing facts cached by earlier sessions"
+  }
+
+  fn run (&self, _args: &str, session: &mut Session) -> Result<String, Box<dyn std::error::Error>>
+  {
+    session.context.refresh(session.executor, session.model.as_ref())
+      .map_err(|e| format!("failed to refresh context: {e}"))?;
+    Ok(session.context.os.clone())
+  }
+}
+
+impl Builtin for ModelBuiltin
+{
+  fn name (&self) -> &'static str
+  {
+    "model"
+  }
+
+  fn usage (&self) -> &'static str
+  {
+    "[backend]"
+  }
+
+  fn description (&self) -> &'static str
+  {
+    "show the current backend, or switch to gpt4, gpt35, claude, claude-haiku, claude-sonnet, claude-opus, local <path> or endpoint <url>"
+  }
+
+  fn run (&self, args: &str, session: &mut Session) -> Result<String, Box<dyn std::error::Error>>
+  {
+    if args.is_empty() {
+      return Ok(format!("current backend: {}", session.model.name()));
+    }
+
+    *session.model = switch_model(session.context, args, session.usage)
+      .map_err(|e| format!("failed to switch backend: {e}"))?;
+    Ok(format!("switched backend to {}", session.model.name()))
+  }
+}
+
+impl Builtin for UsageBuiltin
+{
+  fn name (&self) -> &'static str
+  {
+    "usage"
+  }
+
+  fn usage (&self) -> &'static str
+  {
+    ""
+  }
+
+  fn description (&self) -> &'static str
+  {
+    "show the tokens consumed by remote models this session and today, and their cost"
+  }
+
+  fn run (&self, _args: &str, session: &mut Session) -> Result<String, Box<dyn std::error::Error>>
+  {
+    Ok(session.usage.lock().unwrap().summary())
+  }
+}
+
+impl Builtin for FixBuiltin
+{
+  fn name (&self) -> &'static str
+  {
+    "fix"
+  }
+
+  fn usage (&self) -> &'static str
+  {
+    "[what was wrong]"
+  }
+
+  fn description (&self) -> &'static str
+  {
+    "ask the model for a better command when the last one ran but didn't do what you wanted"
+  }
+
+  fn run (&self, args: &str, session: &mut Session) -> Result<String, Box<dyn std::error::Error>>
+  {
+    let last = session.last_run.as_ref().ok_or("no command has been run yet")?;
+    let complaint = if args.is_empty() { DEFAULT_COMPLAINT } else { args };
+
+    session.context.details = session.providers.gather(session.context, session.executor);
+    let command = wait_for("thinking", session.model.revise_command(session.context, &last.request, &last.command, &last.output, complaint))
+      .map_err(|e| format!("model request failed: {e}"))?;
+
+    // The shell proposes the revised command for confirmation, as it would any other
+    *session.next = Some(NextAction::Command(command));
+    Ok(String::new())
+  }
+}
+
+impl Builtin for DoBuiltin
+{
+  fn name (&self) -> &'static str
+  {
+    "do"
+  }
+
+  fn usage (&self) -> &'static str
+  {
+    "<goal>"
+  }
+
+  fn description (&self) -> &'static str
+  {
+    "work towards a goal over several commands, confirming each one, such as :do free up port 8080"
+  }
+
+  fn run (&self, args: &str, session: &mut Session) -> Result<String, Box<dyn std::error::Error>>
+  {
+    if args.is_empty() {
+      return Err(format!("usage: {BUILTIN_PREFIX}do <goal>").into());
+    }
+
+    *session.next = Some(NextAction::Goal(args.to_string()));
+    Ok(String::new())
+  }
+}
+
+impl Builtins
+{
+  /// The registry of nl-sh's standard builtins
+  pub fn new () -> Self
+  {
+    Builtins {
+      builtins: vec![
+        Box::new(HistoryBuiltin),
+        Box::new(ConfigBuiltin),
+        Box::new(ContextBuiltin),
+        Box::new(RefreshContextBuiltin),
+        Box::new(ModelBuiltin),
+        Box::new(UsageBuiltin),
+        Box::new(FixBuiltin),
+        Box::new(DoBuiltin),
+      ]
+    }
+  }
+
+  /// Adds a builtin, replacing any existing one of the same name
+  pub fn register (&mut self, builtin: Box<dyn Builtin>)
+  {
+    self.builtins.retain(|existing| existing.name() != builtin.name());
+    self.builtins.push(builtin);
+  }
+
+  /// The names of the builtins, with their prefix, as in `:help`
+  pub fn names (&self) -> Vec<String>
+  {
+    let mut names: Vec<String> = std::iter::once(HELP.0).chain(self.builtins.iter().map(|builtin| builtin.name()))
+      .map(|name| format!("{BUILTIN_PREFIX}{name}"))
+      .collect();
+    names.sort();
+    names
+  }
+
+  /// Whether the input names a builtin, such as `:help`. The prefix must be followed directly by a name, since the
+  /// POSIX `:` command is commonly followed by a space, as in `: > file`.
+  pub fn is_builtin (input: &str) -> bool
+  {
+    let mut chars = input.trim_start().chars();
+    chars.next() == Some(BUILTIN_PREFIX) && chars.next().is_some_and(|c| c.is_ascii_alphabetic())
+  }
+
+  /// Runs the builtin named by the input, returning its output
+  pub fn dispatch (&self, input: &str, session: &mut Session) -> Result<String, Box<dyn std::error::Error>>
+  {
+    let line = input.trim().trim_start_matches(BUILTIN_PREFIX);
+    let (name, args) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+
+    if name == HELP.0 {
+      return Ok(self.help());
+    }
+
+    match self.builtins.iter().find(|builtin| builtin.name() == name) {
+      Some(builtin) => builtin.run(args.trim(), session),
+      None => Err(format!("unknown builtin {BUILTIN_PREFIX}{name}; try {BUILTIN_PREFIX}help").into())
+    }
+  }
+
+  fn help (&self) -> String
+  {
+    let mut entries = vec![HELP];
+    entries.extend(self.builtins.iter().map(|builtin| (builtin.name(), builtin.usage(), builtin.description())));
+
+    let synopses: Vec<String> = entries.iter()
+      .map(|(name, usage, _)| format!("{BUILTIN_PREFIX}{name} {usage}").trim_end().to_string())
+      .collect();
+    let width = synopses.iter().map(|synopsis| synopsis.len()).max().unwrap_or(0);
+
+    synopses.iter().zip(entries.iter())
+      .map(|(synopsis, (_, _, description))| format!("{synopsis:<width$}  {description}"))
+      .collect::<Vec<String>>()
+      .join("\n")
+  }
+}
+
+impl Default for Builtins
+{
+  fn default () -> Self
+  {
+    Builtins::new()
+  }
+}
+
+/// Constructs the backend named by a `:model` builtin, such as `claude-opus` or `local ./mistral.gguf`, along with any
+/// configured fallbacks. Offline mode permits only local backends, as at startup.
+fn
+switch_model (context: &Context, spec: &str, usage: &Arc<Mutex<UsageLedger>>) -> Result<Box<dyn Model>, Box<dyn std::error::Error>>
+{
+  let model_type = ModelType::parse(spec, &context.config)
+    .ok_or_else(|| format!("unknown backend \"{spec}\"; expected gpt4, gpt35, claude, claude-haiku, claude-sonnet, claude-opus, local <path> or endpoint <url>"))?;
+
+  build_model_chain(&model_type, &context.config, usage)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::shell::*;
+
+  /// A backend which always suggests `ls`
+  struct Echo;
+
+  impl Model for Echo {
+    fn init_prompt (&self, _input: &str) -> ModelResponse<'_>
+    {
+      Box::pin(async { Ok("ls".to_string()) })
+    }
+
+    fn ask_model (&self, _context: &Context, input: &str) -> ModelResponse<'_>
+    {
+      self.init_prompt(input)
+    }
+
+    fn attempt_correction (&self, _context: &Context, input: &str, _command: &str, _output: &CommandOutput) -> ModelResponse<'_>
+    {
+      self.init_prompt(input)
+    }
+
+    fn revise_command (&self, _context: &Context, input: &str, _command: &str, _output: &CommandOutput, _complaint: &str) -> ModelResponse<'_>
+    {
+      self.init_prompt(input)
+    }
+
+    fn next_step (&self, _context: &Context, goal: &str, _steps: &[Step]) -> ModelResponse<'_>
+    {
+      self.init_prompt(goal)
+    }
+
+    fn name (&self) -> String
+    {
+      "echo".to_string()
+    }
+  }
+
+  /// Runs a builtin against a fresh session, returning its output and the resulting context
+  fn run (input: &str, context: Context) -> (Result<String, Box<dyn std::error::Error>>, Context)
+  {
+    let (result, context, _) = run_after(input, context, None);
+    (result, context)
+  }
+
+  /// Runs a builtin after the given command has run, also returning anything it hands back to the shell
+  fn run_after (input: &str, mut context: Context, last_run: Option<LastRun>)
+    -> (Result<String, Box<dyn std::error::Error>>, Context, Option<NextAction>)
+  {
+    let mut model: Box<dyn Model> = Box::new(Echo);
+    let executor = MockCommandExecutorInterface::new();
+    let mut providers = ContextProviders::from_config(&Config::parse("context.tools = false\ncontext.git = false\ncontext.listing = false"));
+    let usage = Arc::new(Mutex::new(UsageLedger::from_config(&context.config)));
+    let mut next = None;
+
+    let mut session = Session { context: &mut context, model: &mut model, executor: &executor, providers: &mut providers, usage: &usage,
+      last_run: &last_run, next: &mut next };
+    let result = Builtins::new().dispatch(input, &mut session);
+    (result, context, next)
+  }
+
+  #[test]
+  fn test_is_builtin () {
+    assert!(Builtins::is_builtin(":help"));
+    assert!(Builtins::is_builtin("  :model claude"));
+    assert!(!Builtins::is_builtin(": > file.txt"));
+    assert!(!Builtins::is_builtin("ls :foo"));
+  }
+
+  #[test]
+  fn test_help () {
+    let (output, _) = run(":help", Context::for_test("/home"));
+    let output = output.unwrap();
+    assert!(output.starts_with(":help "));
+    assert!(output.contains(":config [key [value]]  show all settings"));
+  }
+
+  #[test]
+  fn test_unknown_builtin () {
+    let (output, _) = run(":frobnicate now", Context::for_test("/home"));
+    assert_eq!(output.unwrap_err().to_string(), "unknown builtin :frobnicate; try :help");
+  }
+
+  #[test]
+  fn test_config () {
+    let (output, context) = run(":config redact.enabled = false", Context::for_test("/home"));
+    assert_eq!(output.unwrap(), "redact.enabled = false for this session; takes effect once the backend is switched with :model");
+    assert!(!context.config.get_bool("redact.enabled", true));
+
+    let (output, context) = run(":config redact.enabled", context);
+    assert_eq!(output.unwrap(), "redact.enabled = false");
+
+    let (output, _) = run(":config offline false", context);
+    assert!(output.is_err());
+
+    let (output, _) = run(":config pager off", Context::for_test("/home"));
+    assert_eq!(output.unwrap(), "pager = off for this session");
+
+    let (output, _) = run(":config classifier.model /models/tiny.gguf", Context::for_test("/home"));
+    assert_eq!(output.unwrap_err().to_string(), "classifier.model can only be set at startup, in ~/.nl-sh/config");
+
+    let mut context = Context::for_test("/home");
+    context.config.set("offline", "true");
+    let (output, context) = run(":config model.fallback gpt4", context);
+    assert_eq!(output.unwrap_err().to_string(), "model.fallback can't be changed in offline mode");
+    assert_eq!(context.config.get("model.fallback"), None);
+  }
+
+  #[test]
+  fn test_context () {
+    let (output, _) = run(":context", Context::for_test("/home"));
+    assert_eq!(output.unwrap(), "kernel: Darwin 23.3.0 arm64\noperating system: macOS 14.3\nshell: /bin/zsh\nworking directory: /home");
+  }
+
+  #[test]
+  fn test_model () {
+    let (output, _) = run(":model", Context::for_test("/home"));
+    assert_eq!(output.unwrap(), "current backend: echo");
+
+    let mut context = Context::for_test("/home");
+    context.config.set("offline", "true");
+    let (output, _) = run(":model claude-opus", context);
+    assert_eq!(output.unwrap_err().to_string(),
+      "failed to switch backend: offline mode only permits a local model, or an endpoint on localhost");
+  }
+
+  #[test]
+  fn test_fix () {
+    let (output, _, next) = run_after(":fix", Context::for_test("/home"), None);
+    assert_eq!(output.unwrap_err().to_string(), "no command has been run yet");
+    assert_eq!(next, None);
+
+    let last_run = LastRun {
+      request: "find my notes".to_string(),
+      command: "find . -name notes".to_string(),
+      output: CommandOutput::from_fields(true, 0, String::new(), String::new())
+    };
+    let (output, _, next) = run_after(":fix they're markdown files", Context::for_test("/home"), Some(last_run));
+    assert_eq!(output.unwrap(), "");
+    assert_eq!(next, Some(NextAction::Command("ls".to_string())));
+  }
+
+  #[test]
+  fn test_do () {
+    let (output, _, next) = run_after(":do", Context::for_test("/home"), None);
+    assert_eq!(output.unwrap_err().to_string(), "usage: :do <goal>");
+    assert_eq!(next, None);
+
+    let (output, _, next) = run_after(":do free up port 8080", Context::for_test("/home"), None);
+    assert_eq!(output.unwrap(), "");
+    assert_eq!(next, Some(NextAction::Goal("free up port 8080".to_string())));
+  }
+}
@@ -0,0 +1,143 @@
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+use crate::context::nl_sh_dir;
+use crate::system::{Coreutils, SystemInfo};
+
+/// Environment facts computed at startup, cached on disk between sessions. Probing the system costs several
+/// process launches (and possibly a model request), so these are reused until the host's kernel changes, or the
+/// user asks for a refresh.
+#[derive(Clone, Debug, PartialEq)]
+pub struct CachedContext {
+  pub hostname: String,
+  pub kernel: String,
+  pub uname: String,
+  pub shell: String,
+  pub os: String,
+  pub system: SystemInfo,
+}
+
+impl CachedContext
+{
+  /// Loads the cached facts for this host, provided they were computed under the same kernel version and shell
+  pub fn load (hostname: &str, kernel: &str, shell: &str) -> Option<CachedContext>
+  {
+    let contents = fs::read_to_string(cache_path(hostname)?).ok()?;
+    CachedContext::parse(&contents).filter(|cached| cached.matches(hostname, kernel, shell))
+  }
+
+  pub fn save (&self) -> io::Result<()>
+  {
+    let path = cache_path(&self.hostname)
+      .ok_or_else(|| io::Error::other("could not determine home directory"))?;
+
+    if let Some(dir) = path.parent() {
+      fs::create_dir_all(dir)?;
+    }
+
+    fs::write(path, self.serialize())
+  }
+
+  pub fn matches (&self, hostname: &str, kernel: &str, shell: &str) -> bool
+  {
+    self.hostname == hostname && self.kernel == kernel && self.shell == shell
+  }
+
+  /// Parses the `key=value` lines written by `serialize`
+  pub fn parse (contents: &str) -> Option<CachedContext>
+  {
+    let field = |key: &str| contents.lines()
+      .find_map(|line| line.strip_prefix(key).and_then(|rest| rest.strip_prefix('=')))
+      .map(|value| value.to_string());
+    let optional = |key: &str| field(key).filter(|value| !value.is_empty());
+
+    Some(CachedContext {
+      hostname: field("hostname")?,
+      kernel: field("kernel")?,
+      uname: field("uname")?,
+      shell: field("shell")?,
+      os: field("os")?,
+      system: SystemInfo {
+        name: field("system.name").unwrap_or_default(),
+        package_manager: optional("system.package_manager"),
+        init_system: optional("system.init_system"),
+        container: optional("system.container"),
+        wsl: field("system.wsl").is_some_and(|wsl| wsl == "true"),
+        coreutils: field("system.coreutils").map(|c| Coreutils::parse(&c)).unwrap_or_default(),
+      }
+    })
+  }
+
+  pub fn serialize (&self) -> String
+  {
+    let optional = |value: &Option<String>| value.clone().unwrap_or_default();
+
+    [
+      ("hostname", self.hostname.clone()),
+      ("kernel", self.kernel.clone()),
+      ("uname", self.uname.clone()),
+      ("shell", self.shell.clone()),
+      ("os", self.os.clone()),
+      ("system.name", self.system.name.clone()),
+      ("system.package_manager", optional(&self.system.package_manager)),
+      ("system.init_system", optional(&self.system.init_system)),
+      ("system.container", optional(&self.system.container)),
+      ("system.wsl", self.system.wsl.to_string()),
+      ("system.coreutils", self.system.coreutils.as_str().to_string()),
+    ]
+      .iter()
+      .map(|(key, value)| format!("{key}={}\n", value.replace('\n', " ")))
+      .collect()
+  }
+}
+
+fn
+cache_path (hostname: &str) -> Option<PathBuf>
+{
+  nl_sh_dir().map(|dir| dir.join("cache").join(format!("context-{hostname}")))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn cached () -> CachedContext
+  {
+    CachedContext {
+      hostname: "devbox".to_string(),
+      kernel: "6.5.0-21-generic".to_string(),
+      uname: "Linux 6.5.0-21-generic x86_64".to_string(),
+      shell: "/bin/bash".to_string(),
+      os: "Ubuntu 22.04.4 LTS; package manager: apt".to_string(),
+      system: SystemInfo {
+        name: "Ubuntu 22.04.4 LTS".to_string(),
+        package_manager: Some("apt".to_string()),
+        init_system: None,
+        container: Some("docker".to_string()),
+        wsl: false,
+        coreutils: Coreutils::GNU,
+      }
+    }
+  }
+
+  #[test]
+  fn test_round_trip () {
+    let cached = cached();
+    assert_eq!(CachedContext::parse(&cached.serialize()), Some(cached));
+  }
+
+  #[test]
+  fn test_matches () {
+    let cached = cached();
+    assert!(cached.matches("devbox", "6.5.0-21-generic", "/bin/bash"));
+    assert!(!cached.matches("devbox", "6.5.0-25-generic", "/bin/bash"));
+    assert!(!cached.matches("otherbox", "6.5.0-21-generic", "/bin/bash"));
+    assert!(!cached.matches("devbox", "6.5.0-21-generic", "/bin/zsh"));
+  }
+
+  #[test]
+  fn test_parse_incomplete () {
+    assert_eq!(CachedContext::parse("hostname=devbox\nkernel=6.5.0\n"), None);
+  }
+}
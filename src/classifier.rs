@@ -0,0 +1,278 @@
+use llama_cpp_rs::LLama;
+use llama_cpp_rs::options::PredictOptions;
+
+use crate::config::Config;
+use crate::lexer::{is_assignment, split_commands, tokenize, TokenKind};
+use crate::local::local_llm;
+
+/// Some POSIX commands have verb-y characteristics, and for these, we'll let the LLM determine
+/// whether the user intent is to run a specfic command, or whether the request is something
+/// akin to "diff these two files" or "sort and print the text in a, b, and c". Their input
+/// needs some evidence of being a command, such as a flag or path, before it's treated as one.
+static COMMAND_EXCEPTIONS: &[&str] = &["alias", "cat", "diff", "expand", "find", "kill", "link",
+  "list", "log", "print", "read", "sort", "split", "strip", "touch", "type", "what", "which", "who"];
+
+/// Words which rarely appear as command arguments, but are common in English: articles, pronouns, prepositions,
+/// question words and auxiliary verbs
+static ENGLISH_WORDS: &[&str] = &["a", "an", "the", "i", "me", "my", "mine", "we", "our", "us", "you", "your",
+  "it", "its", "this", "that", "these", "those", "them", "their", "there", "all", "every", "each", "any", "some",
+  "of", "in", "into", "on", "onto", "for", "with", "without", "by", "from", "than", "about", "at", "which", "what",
+  "how", "why", "where", "who", "whose", "when", "is", "are", "was", "were", "be", "been", "do", "does", "did", "can",
+  "could", "should", "would", "will", "please", "and", "or", "but", "if", "then", "so", "not", "most", "more", "less"];
+
+/// Words which are weaker evidence of English, since they're plausible arguments: common nouns and particles
+static WEAK_ENGLISH_WORDS: &[&str] = &["up", "out", "over", "files", "file", "folder", "folders", "directory",
+  "directories", "dir", "process", "processes", "everything", "anything", "something", "size", "sizes", "largest",
+  "biggest", "smallest", "newest", "oldest", "recent", "disk", "space", "memory", "machine", "computer", "system",
+  "current", "running", "contents", "lines", "words", "name", "names"];
+
+/// Scores at or above this are classified as commands
+const COMMAND_THRESHOLD: i32 = 1;
+
+/// Scores within this distance of the threshold are uncertain, and referred to the classifier model if there is one
+const UNCERTAINTY: i32 = 1;
+
+/// Decides whether a line of input is a command to run as-is, or a natural-language request for the model. This is
+/// a best-effort attempt to short-circuit requests to an LLM, to improve performance for the most obvious commands.
+///
+/// Input is tokenized with shell quoting rules, and scored: shell syntax, flags and paths count towards a command,
+/// while English sentence structure counts against. Uncertain cases can optionally be referred to a small local
+/// model, given by the `classifier.model` setting.
+pub struct Classifier {
+  model: Option<LLama>,
+}
+
+impl Classifier
+{
+  /// A classifier using heuristics alone
+  pub fn new () -> Self
+  {
+    Classifier { model: None }
+  }
+
+  pub fn from_config (config: &Config) -> Self
+  {
+    let model = config.get("classifier.model").and_then(|path| match local_llm(path) {
+      Ok(model) => Some(model),
+      Err(e) => {
+        eprintln!("ignoring classifier.model: {e}");
+        None
+      }
+    });
+
+    Classifier { model }
+  }
+
+  /// Whether the input is likely a command, given a way to check whether a command of some name exists
+  pub fn is_command (&self, input: &str, exists: &dyn Fn(&str) -> bool) -> bool
+  {
+    let score = match score(input, exists) {
+      Some(score) => score,
+      None => return false
+    };
+
+    match &self.model {
+      Some(model) if (score - COMMAND_THRESHOLD).abs() <= UNCERTAINTY => {
+        ask_classifier_model(model, input).unwrap_or(score >= COMMAND_THRESHOLD)
+      },
+      _ => score >= COMMAND_THRESHOLD
+    }
+  }
+}
+
+impl Default for Classifier
+{
+  fn default () -> Self
+  {
+    Classifier::new()
+  }
+}
+
+/// Scores the input; positive scores suggest a command, and negative ones English. Returns `None` when the input
+/// can't be a command at all, because it doesn't name one.
+fn
+score (input: &str, exists: &dyn Fn(&str) -> bool) -> Option<i32>
+{
+  let tokens = tokenize(input);
+  let commands = split_commands(input);
+  let first = commands.first()?;
+  let name = first.name()?.text.clone();
+
+  // Explicit paths to executables are always commands
+  if name.contains('/') {
+    return Some(COMMAND_THRESHOLD + 1);
+  }
+
+  let lowercase = name.to_lowercase();
+  let exception = COMMAND_EXCEPTIONS.contains(&lowercase.as_str());
+  if exception && first.words.len() == 1 && tokens.len() == 1 {
+    // A verb-y command alone, such as `alias`, has nothing to suggest it's a command
+    return None;
+  }
+  if !exists(&lowercase) {
+    return None;
+  }
+
+  let mut score = if exception { -1 } else { 0 };
+
+  // Shell syntax: pipelines, lists and redirections, or leading variable assignments
+  if tokens.iter().any(|token| token.kind != TokenKind::Word) {
+    score += 3;
+  }
+  if first.words.first().is_some_and(|word| is_assignment(&word.text)) {
+    score += 2;
+  }
+
+  let args: Vec<_> = first.words.iter().skip_while(|word| is_assignment(&word.text)).skip(1).collect();
+  let mut evidence = 0;
+  let mut english = 0;
+  for arg in &args {
+    let text = arg.text.as_str();
+    if arg.quoted {
+      // Quoted arguments hold whatever the command needs, including English
+      evidence += 1;
+    } else if (text.starts_with('-') && text.len() > 1) || looks_like_path(text) || text.contains(['$', '=']) {
+      // Flags, paths, variables and settings
+      evidence += 2;
+    } else if text.chars().all(|c| c.is_ascii_digit()) {
+      evidence += 1;
+    } else if ENGLISH_WORDS.contains(&text.to_lowercase().as_str()) {
+      english += 2;
+    } else if WEAK_ENGLISH_WORDS.contains(&text.to_lowercase().as_str()) {
+      english += 1;
+    }
+  }
+
+  // Words which don't look like English, such as subcommands (`make install`), are mild evidence of a command
+  if english == 0 {
+    evidence += if args.is_empty() { 1 } else { 2 };
+  }
+
+  // Sentence punctuation, and apostrophes which leave a quote unbalanced, as in "what's my ip"
+  let trimmed = input.trim_end();
+  if trimmed.ends_with('?') || (trimmed.ends_with('.') && !trimmed.ends_with("..") && args.len() > 2) {
+    english += 3;
+  }
+  if has_apostrophe(input) {
+    english += 3;
+  }
+
+  score += evidence.min(6) - english;
+  Some(score)
+}
+
+/// Whether an argument looks like a path, file name or glob
+fn
+looks_like_path (text: &str) -> bool
+{
+  if text.contains('/') || text.starts_with('~') || text.starts_with('.') || text.contains(['*', '?', '[']) {
+    return true;
+  }
+
+  // A file name with an extension, such as `notes.txt` or `example.com`
+  match text.rsplit_once('.') {
+    Some((stem, extension)) => !stem.is_empty() && !extension.is_empty() && extension.len() <= 5
+      && extension.chars().all(|c| c.is_ascii_alphanumeric()),
+    None => false
+  }
+}
+
+/// Whether the input contains an English contraction or possessive which leaves single quotes unbalanced
+fn
+has_apostrophe (input: &str) -> bool
+{
+  let chars: Vec<char> = input.chars().collect();
+  let contraction = chars.windows(3).any(|w| w[0].is_alphabetic() && w[1] == '\'' && w[2].is_alphabetic());
+  contraction && chars.iter().filter(|&&c| c == '\'').count() % 2 == 1
+}
+
+/// Asks the classifier model to settle an uncertain case
+fn
+ask_classifier_model (model: &LLama, input: &str) -> Option<bool>
+{
+  let prompt = format!(
+    "Classify the following input to a terminal as either COMMAND, if it's a shell command to run exactly as written,
+    or REQUEST, if it describes a task in natural language. Respond with a single word.
+    Input: {input}
+    Classification:");
+
+  let options = PredictOptions { tokens: 4, ..Default::default() };
+  let response = model.predict(prompt, options).ok()?.to_uppercase();
+
+  if response.contains("COMMAND") {
+    Some(true)
+  } else if response.contains("REQUEST") {
+    Some(false)
+  } else {
+    None
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  /// Commands assumed to be installed when classifying the corpus
+  static INSTALLED: &[&str] = &["alias", "apt", "awk", "brew", "cargo", "cat", "cd", "chmod", "chown", "cp", "curl",
+    "date", "df", "diff", "docker", "du", "echo", "env", "export", "file", "find", "for", "free", "git", "go", "grep",
+    "head", "history", "htop", "kill", "kubectl", "less", "ln", "ls", "make", "man", "mkdir", "mv", "npm", "open",
+    "ping", "ps", "pwd", "python3", "rename", "rm", "rsync", "scp", "sed", "sort", "ssh", "sudo", "tail", "tar", "time",
+    "top", "touch", "type", "uname", "uptime", "wc", "wget", "which", "who", "xargs", "yes", "zip"];
+
+  fn installed (name: &str) -> bool
+  {
+    INSTALLED.contains(&name)
+  }
+
+  #[test]
+  fn test_commands () {
+    let classifier = Classifier::new();
+    assert!(classifier.is_command("ls", &installed));
+    assert!(classifier.is_command("make install", &installed));
+    assert!(classifier.is_command("find . -name '*.rs'", &installed));
+    assert!(classifier.is_command("kill -9 1234", &installed));
+    assert!(classifier.is_command("git commit -m 'fix the bug in my code'", &installed));
+    assert!(classifier.is_command("./configure --prefix=/usr", &installed));
+  }
+
+  #[test]
+  fn test_requests () {
+    let classifier = Classifier::new();
+    assert!(!classifier.is_command("make a backup of this dir", &installed));
+    assert!(!classifier.is_command("sort my downloads by size", &installed));
+    assert!(!classifier.is_command("alias", &installed));
+    assert!(!classifier.is_command("what's my ip address", &installed));
+    assert!(!classifier.is_command("show me the largest files", &installed));
+  }
+
+  #[test]
+  fn test_looks_like_path () {
+    assert!(looks_like_path("src/main.rs"));
+    assert!(looks_like_path("notes.txt"));
+    assert!(looks_like_path("~"));
+    assert!(looks_like_path("*.log"));
+    assert!(!looks_like_path("backup"));
+    assert!(!looks_like_path("end."));
+  }
+
+  /// Measures accuracy over the labelled corpus, in which each line is `C` (command) or `N` (natural language),
+  /// a tab, and the input
+  #[test]
+  fn test_corpus_accuracy () {
+    let corpus = include_str!("../tests/data/classifier_corpus.txt");
+    let classifier = Classifier::new();
+
+    let mut total = 0;
+    let mut misclassified = Vec::new();
+    for line in corpus.lines().filter(|line| !line.trim().is_empty() && !line.starts_with('#')) {
+      let (label, input) = line.split_once('\t').unwrap();
+      total += 1;
+      if classifier.is_command(input, &installed) != (label == "C") {
+        misclassified.push(line);
+      }
+    }
+
+    let accuracy = 1.0 - misclassified.len() as f64 / total as f64;
+    assert!(accuracy >= 0.95, "accuracy {accuracy:.3} over {total} inputs; misclassified:\n{}", misclassified.join("\n"));
+  }
+}
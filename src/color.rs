@@ -0,0 +1,157 @@
+use regex::Regex;
+use std::sync::OnceLock;
+
+use crate::environment::Environment;
+use crate::lexer::{split_commands, tokenize, TokenKind};
+use crate::system::Coreutils;
+
+/// Environment variables which ask particular commands to colour their output even though it's captured through a
+/// pipe: `CLICOLOR_FORCE` for BSD `ls`, and `FORCE_COLOR` for Node.js and Python tooling. Each is assigned only for the
+/// command it's meant for, as in `FORCE_COLOR=1 jest`, so that it isn't inherited by anything else the command line
+/// runs, such as a script which writes `ls` output to a file.
+static COLOR_VARIABLES: &[(&str, &[Coreutils], &str)] = &[("ls", &[Coreutils::BSD], "CLICOLOR_FORCE"),
+  ("jest", ALL, "FORCE_COLOR"), ("mocha", ALL, "FORCE_COLOR"), ("vitest", ALL, "FORCE_COLOR"),
+  ("eslint", ALL, "FORCE_COLOR"), ("prettier", ALL, "FORCE_COLOR"), ("tsc", ALL, "FORCE_COLOR"),
+  ("pytest", ALL, "FORCE_COLOR"), ("ruff", ALL, "FORCE_COLOR")];
+
+/// Every coreutils flavour, for commands which don't vary between them
+const ALL: &[Coreutils] = &[Coreutils::GNU, Coreutils::BSD, Coreutils::BusyBox, Coreutils::Unknown];
+
+/// Commands which need a flag to colour their output, with the coreutils flavours known to accept it
+static COLOR_FLAGS: &[(&str, &[Coreutils])] = &[("ls", &[Coreutils::GNU, Coreutils::BusyBox]),
+  ("grep", &[Coreutils::GNU, Coreutils::BSD]), ("egrep", &[Coreutils::GNU, Coreutils::BSD]),
+  ("fgrep", &[Coreutils::GNU, Coreutils::BSD])];
+
+/// Given to git as a command-line setting, which applies to that git command alone
+const GIT_COLOR: &str = "-c color.ui=always";
+
+const ALWAYS: &str = "--color=always";
+
+/// Removes ANSI escape sequences, such as colours and cursor movement, from command output
+pub fn
+strip_ansi (text: &str) -> String
+{
+  static ANSI: OnceLock<Regex> = OnceLock::new();
+  let ansi = ANSI.get_or_init(|| Regex::new(r"\x1b(?:\[[0-9;?]*[ -/]*[@-~]|\][^\x07\x1b]*(?:\x07|\x1b\\)|[@-Z\\-_])").unwrap());
+  ansi.replace_all(text, "").into_owned()
+}
+
+/// Adapts a command line so that the commands within colour their output as they would on a terminal. Known tools are
+/// given `--color=always` (replacing `--color=auto`, as in the common `alias ls='ls --color=auto'`), git is given a
+/// `color.ui` setting, and others are hinted through a variable assigned for that command alone. Variables the user
+/// has set themselves are left alone.
+///
+/// Command lines which pipe or redirect output are left as they are, since colour codes would corrupt whatever
+/// consumes it, as would be the case for `ls | wc -l`.
+pub fn
+color_hints (command: &str, coreutils: Coreutils, env: &Environment) -> String
+{
+  let tokens = tokenize(command);
+  let pipes_or_redirects = tokens.iter().any(|token| {
+    token.kind == TokenKind::Redirect || (token.kind == TokenKind::Operator && token.text.starts_with('|') && token.text != "||")
+  });
+  if pipes_or_redirects || command.contains("$(") || command.contains('`') {
+    return command.to_string();
+  }
+
+  // Edits to the command line, as byte ranges and their replacements, applied from the end so offsets stay valid
+  let mut edits = Vec::new();
+  for simple in split_commands(command) {
+    let name = match simple.name() {
+      Some(name) => name,
+      None => continue
+    };
+
+    let assigned = |variable: &str| simple.words.iter().any(|word| word.span.start < name.span.start && word.text.starts_with(&format!("{variable}=")));
+    if let Some((_, _, variable)) = COLOR_VARIABLES.iter().find(|(command, flavours, _)| *command == name.text && flavours.contains(&coreutils)) {
+      if env.get(variable).is_none() && !assigned(variable) {
+        edits.push((name.span.start..name.span.start, format!("{variable}=1 ")));
+      }
+    }
+
+    if name.text == "git" {
+      if env.get("GIT_CONFIG_COUNT").is_none() && !simple.words.iter().any(|word| word.text.starts_with("color.")) {
+        edits.push((name.span.end..name.span.end, format!(" {GIT_COLOR}")));
+      }
+      continue;
+    }
+
+    let flavours = match COLOR_FLAGS.iter().find(|(command, _)| *command == name.text) {
+      Some((_, flavours)) => flavours,
+      None => continue
+    };
+
+    if !flavours.contains(&coreutils) {
+      continue;
+    }
+
+    let color_flags: Vec<_> = simple.words.iter().filter(|word| word.text.starts_with("--color") || word.text.starts_with("--colour")).collect();
+    match color_flags.as_slice() {
+      [] => edits.push((name.span.end..name.span.end, format!(" {ALWAYS}"))),
+      flags => edits.extend(flags.iter()
+        .filter(|flag| flag.text.ends_with("=auto"))
+        .map(|flag| (flag.span.clone(), ALWAYS.to_string())))
+    }
+  }
+
+  let mut hinted = command.to_string();
+  edits.sort_by_key(|(span, _)| std::cmp::Reverse(span.start));
+  for (span, replacement) in edits {
+    hinted.replace_range(span, &replacement);
+  }
+
+  hinted
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_strip_ansi () {
+    assert_eq!(strip_ansi("\x1b[01;34msrc\x1b[0m  \x1b[1;32mrun.sh\x1b[m\n"), "src  run.sh\n");
+    assert_eq!(strip_ansi("\x1b]8;;http://example.com\x07link\x1b]8;;\x07 \x1b[2K\rdone"), "link \rdone");
+    assert_eq!(strip_ansi("plain text"), "plain text");
+  }
+
+  #[test]
+  fn test_color_flags () {
+    let env = Environment::new();
+    assert_eq!(color_hints("ls -la", Coreutils::GNU, &env), "ls --color=always -la");
+    assert_eq!(color_hints("ls --color=auto -l && grep -r foo .", Coreutils::GNU, &env),
+      "ls --color=always -l && grep --color=always -r foo .");
+    assert_eq!(color_hints("grep --color=never foo x", Coreutils::GNU, &env), "grep --color=never foo x");
+  }
+
+  #[test]
+  fn test_pipes_and_redirects () {
+    let env = Environment::new();
+    for command in ["ls | wc -l", "ls > out.txt", "echo $(ls)", "ls 2>&1"] {
+      assert_eq!(color_hints(command, Coreutils::GNU, &env), command);
+    }
+
+    assert_eq!(color_hints("make || ls", Coreutils::GNU, &env), "make || ls --color=always");
+  }
+
+  #[test]
+  fn test_color_variables () {
+    let env = Environment::new();
+    assert_eq!(color_hints("ls -la", Coreutils::BSD, &env), "CLICOLOR_FORCE=1 ls -la");
+    assert_eq!(color_hints("cd web && jest src", Coreutils::GNU, &env), "cd web && FORCE_COLOR=1 jest src");
+    assert_eq!(color_hints("FORCE_COLOR=0 jest", Coreutils::GNU, &env), "FORCE_COLOR=0 jest");
+
+    // Anything else the command line runs sees no colour variables
+    assert_eq!(color_hints("./release.sh", Coreutils::GNU, &env), "./release.sh");
+
+    let mut env = Environment::new();
+    env.vars.insert("FORCE_COLOR".to_string(), "0".to_string());
+    assert_eq!(color_hints("jest", Coreutils::GNU, &env), "jest");
+  }
+
+  #[test]
+  fn test_git_color () {
+    let env = Environment::new();
+    assert_eq!(color_hints("git log --oneline", Coreutils::GNU, &env), "git -c color.ui=always log --oneline");
+    assert_eq!(color_hints("git -c color.diff=never diff", Coreutils::GNU, &env), "git -c color.diff=never diff");
+  }
+}
@@ -1,39 +1,48 @@
-use std::io::Error;
-use std::{io, process};
+use std::io::{Error, Read};
+use std::os::unix::process::CommandExt;
+use std::process::{self, Stdio};
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::color::strip_ansi;
+use crate::environment::Environment;
 
 #[cfg(test)]
 use mockall::{automock, predicate::*};
 
+/// How much of an output is inspected to decide whether it's binary
+const BINARY_SAMPLE: usize = 8000;
+
 #[derive(Clone)]
 pub struct CommandOutput {
   pub success: bool,
   pub status_code: i32,
+  /// Standard output as text, with any colours and other escape sequences removed. Invalid UTF-8 is replaced, and
+  /// binary output is replaced by a summary such as "binary data, 3.2 MB"; so this is always safe to show a model.
   pub stdout: String,
-  pub stderr: String
+  pub stderr: String,
+  /// Standard output exactly as the command wrote it, for display on the terminal
+  pub raw_stdout: Vec<u8>,
+  pub raw_stderr: Vec<u8>,
+  /// Whether standard output is binary data, which shouldn't be written to the terminal
+  pub binary: bool
 }
 
 /// Captures the output of a comamnd executed within the underlying shell. This includes the status code, the
 /// standard output, and standard error output. `CommandOutput` resembles `process::Output` but also handles 
 /// byte-vector to UTF8 string conversion when accessing outputs.
 impl CommandOutput {
-  pub fn from (output: process::Output) -> io::Result<CommandOutput>
+  pub fn from (output: process::Output) -> CommandOutput
   {
-    let stdout = std::str::from_utf8(&output.stdout);
-    let stderr = std::str::from_utf8(&output.stderr);
-
-    match (stdout, stderr) {
-      (Ok(stdout), Ok(stderr)) => {
-        Ok(
-          CommandOutput {
-            success: output.status.success(),
-            status_code: output.status.code().unwrap_or(-1),
-            stdout: stdout.to_string(),
-            stderr: stderr.to_string()
-          }
-        )
-     },
-      (Err(e), _) => Err(io::Error::new(io::ErrorKind::Other, e)),
-      (_, Err(e)) => Err(io::Error::new(io::ErrorKind::Other, e))
+    CommandOutput {
+      success: output.status.success(),
+      status_code: output.status.code().unwrap_or(-1),
+      stdout: output_text(&output.stdout),
+      stderr: output_text(&output.stderr),
+      binary: is_binary(&output.stdout),
+      raw_stdout: output.stdout,
+      raw_stderr: output.stderr
     }
   }
 
@@ -41,20 +50,113 @@ impl CommandOutput {
   pub fn from_fields (success: bool, status_code: i32, stdout: String, stderr: String) -> CommandOutput
   {
     CommandOutput {
-      success: success,
-      status_code: status_code,
-      stdout: stdout,
-      stderr: stderr
+      success,
+      status_code,
+      raw_stdout: stdout.as_bytes().to_vec(),
+      raw_stderr: stderr.as_bytes().to_vec(),
+      binary: false,
+      stdout,
+      stderr
     }
   }
 }
 
+/// Converts output to text: lossily, as it may be in another encoding such as Latin-1, and summarised if it's binary
+fn
+output_text (bytes: &[u8]) -> String
+{
+  if is_binary(bytes) {
+    format!("binary data, {}", describe_size(bytes.len()))
+  } else {
+    strip_ansi(&String::from_utf8_lossy(bytes))
+  }
+}
+
+/// Whether output looks like binary data rather than text: it contains a NUL byte, as git decides, or is mostly
+/// control characters other than whitespace and escape sequences
+pub fn
+is_binary (bytes: &[u8]) -> bool
+{
+  let sample = &bytes[..bytes.len().min(BINARY_SAMPLE)];
+  if sample.contains(&0) {
+    return true;
+  }
+
+  let control = sample.iter()
+    .filter(|&&b| (b < 0x20 && !matches!(b, b'\t' | b'\n' | b'\r' | 0x08 | 0x0c | 0x1b)) || b == 0x7f)
+    .count();
+  !sample.is_empty() && control * 10 > sample.len() * 3
+}
+
+/// A size in bytes, for people to read, such as "3.2 MB"
+fn
+describe_size (bytes: usize) -> String
+{
+  const UNITS: &[&str] = &["KB", "MB", "GB", "TB"];
+  if bytes < 1024 {
+    return format!("{bytes} bytes");
+  }
+
+  let mut size = bytes as f64 / 1024.0;
+  let mut unit = 0;
+  while size >= 1024.0 && unit < UNITS.len() - 1 {
+    size /= 1024.0;
+    unit += 1;
+  }
+  format!("{size:.1} {}", UNITS[unit])
+}
+
+/// Runs a command to completion and collects its stdout, unless it takes longer than `timeout`, in which case it's
+/// killed. The command runs in its own session, detached from the terminal, so that nothing it does can take over
+/// the terminal or stop nl-sh; its stdin and stderr are discarded.
+pub fn
+output_within (command: &mut process::Command, timeout: Duration) -> Option<process::Output>
+{
+  command.stdin(Stdio::null()).stdout(Stdio::piped()).stderr(Stdio::null());
+
+  // Only `setsid` runs between fork and exec, which is async-signal-safe
+  unsafe {
+    command.pre_exec(|| {
+      libc::setsid();
+      Ok(())
+    });
+  }
+  let mut child = command.spawn().ok()?;
+
+  // Read concurrently, so that a long output can't fill the pipe and block the command
+  let mut stdout = child.stdout.take()?;
+  let (sender, receiver) = mpsc::channel();
+  thread::spawn(move || {
+    let mut output = Vec::new();
+    let _ = stdout.read_to_end(&mut output);
+    let _ = sender.send(output);
+  });
+
+  let deadline = Instant::now() + timeout;
+  let status = loop {
+    if let Some(status) = child.try_wait().ok()? {
+      break status;
+    }
+    if Instant::now() >= deadline {
+      let _ = child.kill();
+      let _ = child.wait();
+      return None;
+    }
+    thread::sleep(Duration::from_millis(10));
+  };
+
+  // Anything the command left running in the background may still hold its stdout open
+  let stdout = receiver.recv_timeout(deadline.saturating_duration_since(Instant::now())).ok()?;
+  Some(process::Output { status, stdout, stderr: Vec::new() })
+}
+
 /// A `CommandExecutor` is responsible for interfacing with underlying system commands; either checking for the existence
-/// of a proposed command by a model, or executing a command and returning its output to the caller.
+/// of a proposed command by a model, or executing a command and returning its output to the caller. Both run with the
+/// given `Environment` applied, so that variables exported earlier in the session are visible.
 #[cfg_attr(test, automock)]
 pub trait CommandExecutorInterface {
-  fn exists(&self, shell: &str, command: &str) -> bool;
-  fn execute(&self, shell: &str, command: &str) -> Result<CommandOutput, Error>;
+  fn exists(&self, shell: &str, command: &str, env: &Environment) -> bool;
+  fn execute(&self, shell: &str, command: &str, env: &Environment) -> Result<CommandOutput, Error>;
 }
 
 pub struct CommandExecutor;
@@ -65,19 +167,17 @@ impl CommandExecutorInterface for CommandExecutor {
   /// 
   /// Which will return success (and a path to the command) if it exists; otherwise, or upon 
   /// error, returns false.
-  fn exists (&self, shell: &str, command: &str) -> bool
+  fn exists (&self, shell: &str, command: &str, env: &Environment) -> bool
   {
-    match std::process::Command::new(shell)
+    let mut process = std::process::Command::new(shell);
+    env.apply(&mut process);
+
+    match process
       .arg("-c")
       .arg(format!("command -v \"{}\"", command))
       .output()
-      .map(|output| CommandOutput::from(output)) {
-        Ok(output) => {
-          match output {
-            Ok(output) => output.success,
-            Err(_) => false
-          }
-        },
+      .map(CommandOutput::from) {
+        Ok(output) => output.success,
         Err(_) => false
       }
   }
@@ -86,28 +186,32 @@ impl CommandExecutorInterface for CommandExecutor {
   ///   $ `$SHELL -c <command string>`
   /// 
   /// Returns the collected status code, stdout and stderr wrapped in a `CommandOutput` object
-  fn execute (&self, shell: &str, command: &str) -> Result<CommandOutput, Error> 
+  fn execute (&self, shell: &str, command: &str, env: &Environment) -> Result<CommandOutput, Error> 
   {
-    std::process::Command::new(shell)
+    let mut process = std::process::Command::new(shell);
+    env.apply(&mut process);
+
+    process
       .arg("-c")
-      .arg(format!("{command}"))
+      .arg(command)
       .output()
-      .map(|output| CommandOutput::from(output))?
+      .map(CommandOutput::from)
   }
 }
 
 #[cfg(test)]
 mod tests {
   use super::*;
+  use std::os::unix::process::ExitStatusExt;
 
   #[test]
   fn test_command_exists () {
     let mut mock_executor = MockCommandExecutorInterface::new();
     mock_executor.expect_exists()
-      .with(eq("/bin/bash"), eq("echo"))
-      .returning(|_, _| true);
+      .with(eq("/bin/bash"), eq("echo"), always())
+      .returning(|_, _, _| true);
     
-    assert!(mock_executor.exists("/bin/bash", "echo"));
+    assert!(mock_executor.exists("/bin/bash", "echo", &Environment::new()));
   }
 
   #[test]
@@ -115,12 +219,54 @@ mod tests {
     let mut mock_executor = MockCommandExecutorInterface::new();
     let command_output = CommandOutput::from_fields(true, 0, "Hello".to_string(), "".to_string());
     mock_executor.expect_execute()
-      .with(eq("/bin/bash"), eq("echo Hello"))
-      .returning(move |_, _| Ok(command_output.clone()));
+      .with(eq("/bin/bash"), eq("echo Hello"), always())
+      .returning(move |_, _, _| Ok(command_output.clone()));
 
-    match mock_executor.execute("/bin/bash", "echo Hello") {
+    match mock_executor.execute("/bin/bash", "echo Hello", &Environment::new()) {
       Ok(output) => assert_eq!(output.stdout, "Hello"),
       Err(_) => panic!("Execution should succeed"),
     }
   }
+
+  #[test]
+  fn test_non_utf8_output () {
+    let output = CommandOutput::from(process::Output {
+      status: process::ExitStatus::from_raw(0),
+      stdout: b"caf\xe9 \x1b[1mok\x1b[0m\n".to_vec(),
+      stderr: Vec::new(),
+    });
+
+    assert_eq!(output.stdout, "caf\u{fffd} ok\n");
+    assert!(!output.binary);
+    assert_eq!(output.raw_stdout, b"caf\xe9 \x1b[1mok\x1b[0m\n");
+  }
+
+  #[test]
+  fn test_binary_output () {
+    let mut elf = b"\x7fELF\x02\x01\x01\x00".to_vec();
+    elf.resize(3_355_443, 0);
+    let output = CommandOutput::from(process::Output { status: process::ExitStatus::from_raw(256), stdout: elf, stderr: Vec::new() });
+
+    assert!(output.binary);
+    assert_eq!(output.stdout, "binary data, 3.2 MB");
+    assert_eq!(output.status_code, 1);
+  }
+
+  #[test]
+  fn test_output_within () {
+    let output = output_within(process::Command::new("sh").args(["-c", "echo hello"]), Duration::from_secs(5)).unwrap();
+    assert!(output.status.success());
+    assert_eq!(output.stdout, b"hello\n");
+
+    let started = Instant::now();
+    assert!(output_within(process::Command::new("sleep").arg("10"), Duration::from_millis(100)).is_none());
+    assert!(started.elapsed() < Duration::from_secs(5));
+  }
+
+  #[test]
+  fn test_describe_size () {
+    assert_eq!(describe_size(512), "512 bytes");
+    assert_eq!(describe_size(2048), "2.0 KB");
+    assert_eq!(describe_size(5 * 1024 * 1024 * 1024), "5.0 GB");
+  }
 }
@@ -43,7 +43,7 @@ impl CommandHistory {
     Ok(CommandHistory {
       write_updates: enable_write_updates,
       shell_history_file_path: history_file_path,
-      command_history: command_history,
+      command_history,
     })
   }
 
@@ -56,7 +56,6 @@ impl CommandHistory {
     if self.write_updates {
       // Open the file in append mode
       let mut file = OpenOptions::new()
-        .write(true)
         .append(true)
         .open(&self.shell_history_file_path)?;
 
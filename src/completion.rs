@@ -0,0 +1,427 @@
+use inquire::autocompletion::{Autocomplete, Replacement};
+use inquire::error::CustomUserError;
+use regex::Regex;
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::Duration;
+
+use crate::builtin::Builtins;
+use crate::command::output_within;
+use crate::context::Context;
+use crate::lexer::{is_assignment, tokenize, TokenKind};
+use crate::system::path_executables;
+
+/// Most suggestions listed below the prompt at once
+const MAX_SUGGESTIONS: usize = 20;
+
+/// Most history entries among the suggestions
+const MAX_HISTORY_SUGGESTIONS: usize = 5;
+
+/// Limit on how long a command may take to print its `--help` output
+const HELP_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Characters escaped with a backslash when completing a path
+const SHELL_SPECIAL: &str = " \t'\"\\$&|;()<>*?[]!#`";
+
+/// The `PATH` last scanned, and the executables found on it
+type PathScan = (String, Arc<Vec<String>>);
+
+/// State kept between prompts, so that it isn't rebuilt for every one: the executables on `$PATH`, scanned again only
+/// when `PATH` changes, and the flags of each command, parsed from its `--help` output, so each command runs once
+#[derive(Clone, Default)]
+pub struct CompletionCache {
+  executables: Arc<Mutex<Option<PathScan>>>,
+  flags: Arc<Mutex<HashMap<String, Vec<String>>>>,
+}
+
+impl CompletionCache
+{
+  /// The executables on the given `PATH`, sorted
+  pub fn executables (&self, path: &str) -> Arc<Vec<String>>
+  {
+    let mut cached = self.executables.lock().unwrap();
+    match cached.as_ref() {
+      Some((scanned, executables)) if scanned == path => executables.clone(),
+      _ => {
+        let executables = Arc::new(path_executables(path));
+        *cached = Some((path.to_string(), executables.clone()));
+        executables
+      }
+    }
+  }
+}
+
+/// Completes the word under the cursor in the prompt: executables on `$PATH`, aliases and nl-sh builtins in command
+/// position, flags after a command, and otherwise paths relative to the working directory. Previous command lines
+/// from the history which begin with the input are suggested first.
+#[derive(Clone)]
+pub struct Completer {
+  pwd: String,
+  executables: Arc<Vec<String>>,
+  aliases: Vec<String>,
+  builtins: Vec<String>,
+  history: Vec<String>,
+  /// Set when flags are completed, which `completion.flags` enables
+  cache: Option<CompletionCache>,
+}
+
+/// The word being completed
+#[derive(Debug, PartialEq)]
+struct Word {
+  /// Byte offset of the word within the input
+  start: usize,
+  /// The word with quotes and escapes removed
+  text: String,
+  /// The command the word is an argument to, or `None` if the word is itself in command position
+  command: Option<String>,
+}
+
+impl Completer
+{
+  /// A completer for the current state of the context. Flags are only completed, by running `<command> --help`,
+  /// when `completion.flags` is enabled.
+  pub fn new (context: &Context, builtins: &Builtins, cache: &CompletionCache) -> Self
+  {
+    Completer {
+      pwd: context.pwd.clone(),
+      executables: cache.executables(&context.env.get("PATH").unwrap_or_default()),
+      aliases: context.aliases.table.keys().cloned().collect(),
+      builtins: builtins.names(),
+      history: context.get_command_history(),
+      cache: context.config.get_bool("completion.flags", false).then(|| cache.clone()),
+    }
+  }
+
+  /// Completions for the current word alone. Flags not yet known are only found, by running `<command> --help`, when
+  /// `run_help` is set; that is, when the user presses Tab, rather than for the suggestions shown on every keystroke.
+  fn complete_word (&self, input: &str, run_help: bool) -> (Word, Vec<String>)
+  {
+    let word = current_word(input);
+    let mut completions: Vec<String> = match &word.command {
+      None if word.start == 0 && word.text.starts_with(':') => {
+        self.builtins.iter().filter(|name| name.starts_with(&word.text)).cloned().collect()
+      },
+      None if !word.text.contains('/') && !word.text.starts_with('~') => {
+        self.executables.iter().chain(self.aliases.iter())
+          .filter(|name| name.starts_with(&word.text))
+          .map(|name| escape(name))
+          .collect()
+      },
+      Some(command) if word.text.starts_with('-') => {
+        self.command_flags(command, run_help).into_iter().filter(|flag| flag.starts_with(&word.text)).collect()
+      },
+      _ => complete_path(&self.pwd, &word.text)
+    };
+
+    completions.sort();
+    completions.dedup();
+    (word, completions)
+  }
+
+  /// The flags of an executable on `$PATH`, parsed from its `--help` output; only run if `run_help` is set
+  fn command_flags (&self, command: &str, run_help: bool) -> Vec<String>
+  {
+    let cache = match &self.cache {
+      Some(cache) if self.executables.binary_search(&command.to_string()).is_ok() => cache,
+      _ => return Vec::new()
+    };
+
+    let mut flags = cache.flags.lock().unwrap();
+    match flags.get(command) {
+      Some(known) => known.clone(),
+      None if run_help => flags.entry(command.to_string())
+        .or_insert_with(|| help_text(command).map(|help| parse_help_flags(&help)).unwrap_or_default())
+        .clone(),
+      None => Vec::new()
+    }
+  }
+}
+
+impl Autocomplete for Completer
+{
+  fn get_suggestions (&mut self, input: &str) -> Result<Vec<String>, CustomUserError>
+  {
+    if input.trim().is_empty() {
+      return Ok(Vec::new());
+    }
+
+    let mut suggestions: Vec<String> = self.history.iter()
+      .filter(|line| line.starts_with(input) && line.len() > input.len())
+      .take(MAX_HISTORY_SUGGESTIONS)
+      .cloned()
+      .collect();
+
+    let (word, completions) = self.complete_word(input, false);
+    if !word.text.is_empty() {
+      suggestions.extend(completions.iter().map(|completion| format!("{}{}", &input[..word.start], completion)));
+    }
+
+    let mut unique: Vec<String> = Vec::new();
+    for suggestion in suggestions {
+      if suggestion != input && !unique.contains(&suggestion) {
+        unique.push(suggestion);
+      }
+    }
+
+    unique.truncate(MAX_SUGGESTIONS);
+    Ok(unique)
+  }
+
+  fn get_completion (&mut self, input: &str, highlighted_suggestion: Option<String>) -> Result<Replacement, CustomUserError>
+  {
+    if highlighted_suggestion.is_some() {
+      return Ok(highlighted_suggestion);
+    }
+
+    // As in a shell, a single completion is completed in full, and several are completed as far as they agree
+    let (word, completions) = self.complete_word(input, true);
+    let completion = match completions.as_slice() {
+      [] => return Ok(None),
+      [only] if only.ends_with('/') => only.clone(),
+      [only] => format!("{only} "),
+      _ => common_prefix(&completions)
+    };
+
+    let replacement = format!("{}{}", &input[..word.start], completion);
+    Ok((replacement.len() > input.len()).then_some(replacement))
+  }
+}
+
+/// Finds the word at the end of the input, and the command it belongs to
+fn
+current_word (input: &str) -> Word
+{
+  let tokens = tokenize(input);
+  let (start, text, previous) = match tokens.split_last() {
+    Some((last, previous)) if last.kind == TokenKind::Word && last.span.end == input.len() => {
+      (last.span.start, last.text.clone(), previous)
+    },
+    _ => (input.len(), String::new(), tokens.as_slice())
+  };
+
+  // The command is the first word, other than variable assignments, since the last list or pipeline operator
+  let mut command = None;
+  for token in previous.iter().rev() {
+    match token.kind {
+      TokenKind::Operator => break,
+      TokenKind::Word if !is_assignment(&token.text) => command = Some(token.text.clone()),
+      _ => {}
+    }
+  }
+
+  Word { start, text, command }
+}
+
+/// Completes a path relative to `pwd`; directories are completed with a trailing `/`, and hidden files only when the
+/// word begins with a `.`
+fn
+complete_path (pwd: &str, word: &str) -> Vec<String>
+{
+  let (dir, prefix) = match word.rfind('/') {
+    Some(end) => word.split_at(end + 1),
+    None => ("", word)
+  };
+
+  let path = if let Some(rest) = dir.strip_prefix("~/") {
+    match home::home_dir() {
+      Some(home) => home.join(rest),
+      None => return Vec::new()
+    }
+  } else {
+    Path::new(pwd).join(dir)
+  };
+
+  let entries = match fs::read_dir(&path) {
+    Ok(entries) => entries,
+    Err(_) => return Vec::new()
+  };
+
+  entries.filter_map(Result::ok)
+    .filter_map(|entry| {
+      let name = entry.file_name().into_string().ok()?;
+      if !name.starts_with(prefix) || (name.starts_with('.') && !prefix.starts_with('.')) {
+        return None;
+      }
+
+      let slash = if is_directory(&path.join(&name)) { "/" } else { "" };
+      Some(format!("{}{}{}", escape(dir), escape(&name), slash))
+    })
+    .collect()
+}
+
+fn
+is_directory (path: &Path) -> bool
+{
+  fs::metadata(path).is_ok_and(|metadata| metadata.is_dir())
+}
+
+/// Escapes characters the shell would otherwise interpret
+fn
+escape (text: &str) -> String
+{
+  let mut escaped = String::with_capacity(text.len());
+  for c in text.chars() {
+    if SHELL_SPECIAL.contains(c) {
+      escaped.push('\\');
+    }
+    escaped.push(c);
+  }
+  escaped
+}
+
+/// The longest prefix shared by all of the strings
+fn
+common_prefix (strings: &[String]) -> String
+{
+  let first = match strings.first() {
+    Some(first) => first,
+    None => return String::new()
+  };
+
+  let mut end = first.len();
+  for string in &strings[1..] {
+    end = first.char_indices().zip(string.chars())
+      .take_while(|((_, a), b)| a == b)
+      .map(|((i, a), _)| i + a.len_utf8())
+      .last()
+      .unwrap_or(0)
+      .min(end);
+  }
+
+  first[..end].to_string()
+}
+
+/// Runs `<command> --help`, returning its output, unless it takes too long
+fn
+help_text (command: &str) -> Option<String>
+{
+  let output = output_within(Command::new(command).arg("--help"), HELP_TIMEOUT)?;
+  Some(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// Extracts the flags documented in `--help` output, from lines such as `  -a, --all    do not ignore entries`
+fn
+parse_help_flags (help: &str) -> Vec<String>
+{
+  static FLAG: OnceLock<Regex> = OnceLock::new();
+  let flag = FLAG.get_or_init(|| Regex::new(r"^--?[A-Za-z0-9][A-Za-z0-9_-]*").unwrap());
+
+  let mut flags = Vec::new();
+  for line in help.lines().map(str::trim_start).filter(|line| line.starts_with('-')) {
+    // Flags are listed up to the description, each separated by a comma or space, and possibly followed by an
+    // argument, as in `-o, --output=FILE` or `-n NUM`
+    for word in line.split([',', ' ']).filter(|word| !word.is_empty()) {
+      match flag.find(word) {
+        Some(found) => flags.push(found.as_str().to_string()),
+        None => break
+      }
+    }
+  }
+
+  flags.sort();
+  flags.dedup();
+  flags
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn completer (pwd: &str) -> Completer
+  {
+    Completer {
+      pwd: pwd.to_string(),
+      executables: Arc::new(vec!["cargo".to_string(), "cat".to_string(), "git".to_string(), "grep".to_string()]),
+      aliases: vec!["gst".to_string()],
+      builtins: vec![":config".to_string(), ":context".to_string(), ":help".to_string()],
+      history: vec!["git status".to_string(), "git log --oneline".to_string()],
+      cache: None,
+    }
+  }
+
+  #[test]
+  fn test_current_word () {
+    assert_eq!(current_word("gi"), Word { start: 0, text: "gi".to_string(), command: None });
+    assert_eq!(current_word("FOO=1 cargo b"), Word { start: 12, text: "b".to_string(), command: Some("cargo".to_string()) });
+    assert_eq!(current_word("ls src | gr"), Word { start: 9, text: "gr".to_string(), command: None });
+    assert_eq!(current_word("cat 'my fi"), Word { start: 4, text: "my fi".to_string(), command: Some("cat".to_string()) });
+    assert_eq!(current_word("ls "), Word { start: 3, text: String::new(), command: Some("ls".to_string()) });
+  }
+
+  #[test]
+  fn test_complete_commands () {
+    let mut completer = completer("/");
+    assert_eq!(completer.get_completion("ca", None).unwrap(), None);
+    assert_eq!(completer.get_completion("car", None).unwrap(), Some("cargo ".to_string()));
+    assert_eq!(completer.get_completion("ls | gs", None).unwrap(), Some("ls | gst ".to_string()));
+    assert_eq!(completer.get_completion(":co", None).unwrap(), Some(":con".to_string()));
+    assert_eq!(completer.get_suggestions("git").unwrap(), vec!["git status".to_string(), "git log --oneline".to_string()]);
+    assert_eq!(completer.get_suggestions("g").unwrap(), vec!["git status".to_string(), "git log --oneline".to_string(),
+      "git".to_string(), "grep".to_string(), "gst".to_string()]);
+  }
+
+  #[test]
+  fn test_complete_paths () {
+    let temp_dir = tempfile::tempdir().unwrap();
+    fs::create_dir(temp_dir.path().join("src")).unwrap();
+    fs::write(temp_dir.path().join("src").join("main.rs"), "").unwrap();
+    fs::write(temp_dir.path().join("my notes.txt"), "").unwrap();
+    fs::write(temp_dir.path().join(".hidden"), "").unwrap();
+
+    let mut completer = completer(temp_dir.path().to_str().unwrap());
+    assert_eq!(completer.get_completion("cat s", None).unwrap(), Some("cat src/".to_string()));
+    assert_eq!(completer.get_completion("cat src/m", None).unwrap(), Some("cat src/main.rs ".to_string()));
+    assert_eq!(completer.get_completion("cat my", None).unwrap(), Some("cat my\\ notes.txt ".to_string()));
+    assert_eq!(completer.get_completion("cat .h", None).unwrap(), Some("cat .hidden ".to_string()));
+
+    let mut listing = complete_path(temp_dir.path().to_str().unwrap(), "");
+    listing.sort();
+    assert_eq!(listing, vec!["my\\ notes.txt".to_string(), "src/".to_string()]);
+  }
+
+  #[test]
+  fn test_flags_from_cache () {
+    let cache = CompletionCache::default();
+    cache.flags.lock().unwrap().insert("cargo".to_string(), vec!["--release".to_string(), "--verbose".to_string()]);
+
+    let mut completer = Completer { cache: Some(cache), ..completer("/") };
+    assert_eq!(completer.get_suggestions("cargo --r").unwrap(), vec!["cargo --release".to_string()]);
+
+    // Suggestions never run `--help` for a command whose flags aren't known
+    assert!(completer.get_suggestions("cat --").unwrap().is_empty());
+    assert!(!completer.cache.as_ref().unwrap().flags.lock().unwrap().contains_key("cat"));
+  }
+
+  #[test]
+  fn test_executables_cache () {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let path = temp_dir.path().to_str().unwrap();
+    let cache = CompletionCache::default();
+    assert!(cache.executables(path).is_empty());
+
+    let tool = temp_dir.path().join("tool");
+    fs::write(&tool, "").unwrap();
+    fs::set_permissions(&tool, std::os::unix::fs::PermissionsExt::from_mode(0o755)).unwrap();
+
+    // The scan is kept until PATH changes
+    assert!(cache.executables(path).is_empty());
+    assert_eq!(*cache.executables(&format!("{path}:")), vec!["tool".to_string()]);
+  }
+
+  #[test]
+  fn test_common_prefix () {
+    assert_eq!(common_prefix(&["config".to_string(), "context".to_string()]), "con");
+    assert_eq!(common_prefix(&["café".to_string(), "cafés".to_string()]), "café");
+    assert_eq!(common_prefix(&["a".to_string(), "b".to_string()]), "");
+  }
+
+  #[test]
+  fn test_parse_help_flags () {
+    let help = "Usage: ls [OPTION]... [FILE]...\n\n  -a, --all                  do not ignore entries starting with .\n  \
+      --color[=WHEN]         color the output\n  -w, --width=COLS           set output width\n  -T TABSIZE\n";
+    assert_eq!(parse_help_flags(help), vec!["--all", "--color", "--width", "-T", "-a", "-w"]);
+  }
+}
@@ -0,0 +1,129 @@
+use std::fs;
+use std::path::PathBuf;
+
+use crate::context::nl_sh_dir;
+
+/// User configuration, read from `~/.nl-sh/config`. Each line holds a single `key = value` setting, and
+/// lines beginning with `#` are comments:
+///
+///   # Don't send directory listings to the model
+///   context.listing = false
+///
+/// Some settings accept several values by repeating the key. Unknown keys are ignored.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Config {
+  entries: Vec<(String, String)>,
+}
+
+impl Config
+{
+  pub fn new () -> Self
+  {
+    Config { entries: Vec::new() }
+  }
+
+  /// Loads the user's configuration file; a missing or unreadable file is treated as empty
+  pub fn load () -> Self
+  {
+    config_path()
+      .and_then(|path| fs::read_to_string(path).ok())
+      .map(|contents| Config::parse(&contents))
+      .unwrap_or_default()
+  }
+
+  pub fn parse (contents: &str) -> Self
+  {
+    let entries = contents.lines()
+      .map(|line| line.trim())
+      .filter(|line| !line.is_empty() && !line.starts_with('#'))
+      .filter_map(|line| line.split_once('='))
+      .map(|(key, value)| (key.trim().to_string(), value.trim().to_string()))
+      .collect();
+
+    Config { entries }
+  }
+
+  /// The value of a setting; if it's given more than once, the last value wins
+  pub fn get (&self, key: &str) -> Option<&str>
+  {
+    self.entries.iter().rev().find(|(k, _)| k == key).map(|(_, value)| value.as_str())
+  }
+
+  /// Every value given for a repeatable setting, in the order they appear
+  pub fn get_all (&self, key: &str) -> Vec<&str>
+  {
+    self.entries.iter().filter(|(k, _)| k == key).map(|(_, value)| value.as_str()).collect()
+  }
+
+  pub fn get_bool (&self, key: &str, default: bool) -> bool
+  {
+    match self.get(key) {
+      Some("true") | Some("on") | Some("yes") => true,
+      Some("false") | Some("off") | Some("no") => false,
+      _ => default
+    }
+  }
+
+  pub fn get_usize (&self, key: &str, default: usize) -> usize
+  {
+    self.get(key).and_then(|value| value.parse().ok()).unwrap_or(default)
+  }
+
+  /// A comma-separated list setting, such as `context.tools.list = rg, fd, jq`
+  pub fn get_list (&self, key: &str) -> Option<Vec<String>>
+  {
+    self.get(key).map(|value| {
+      value.split(',').map(|item| item.trim().to_string()).filter(|item| !item.is_empty()).collect()
+    })
+  }
+
+  /// Replaces any existing values of a setting for the rest of the session
+  pub fn set (&mut self, key: &str, value: &str)
+  {
+    self.entries.retain(|(k, _)| k != key);
+    self.entries.push((key.to_string(), value.to_string()));
+  }
+
+  pub fn entries (&self) -> &[(String, String)]
+  {
+    &self.entries
+  }
+}
+
+fn
+config_path () -> Option<PathBuf>
+{
+  nl_sh_dir().map(|dir| dir.join("config"))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_parse () {
+    let config = Config::parse("# comment\ncontext.git = false\n\nredact.pattern = a=b\nredact.pattern = c\nbogus line\n");
+    assert_eq!(config.get("context.git"), Some("false"));
+    assert_eq!(config.get("redact.pattern"), Some("c"));
+    assert_eq!(config.get_all("redact.pattern"), vec!["a=b", "c"]);
+    assert_eq!(config.get("bogus line"), None);
+  }
+
+  #[test]
+  fn test_typed_values () {
+    let config = Config::parse("a = on\nb = no\nc = 12\nd = x, y ,,z");
+    assert!(config.get_bool("a", false));
+    assert!(!config.get_bool("b", true));
+    assert!(config.get_bool("missing", true));
+    assert_eq!(config.get_usize("c", 0), 12);
+    assert_eq!(config.get_usize("a", 7), 7);
+    assert_eq!(config.get_list("d"), Some(vec!["x".to_string(), "y".to_string(), "z".to_string()]));
+  }
+
+  #[test]
+  fn test_set () {
+    let mut config = Config::parse("a = 1\na = 2");
+    config.set("a", "3");
+    assert_eq!(config.get_all("a"), vec!["3"]);
+  }
+}
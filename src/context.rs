@@ -1,17 +1,33 @@
 use regex::Regex;
 use std::env;
 use std::io::{self, Error};
+use std::path::PathBuf;
 
 use crate::args::Args;
+use crate::cache::CachedContext;
+pub use crate::config::*;
 pub use crate::command_history::*;
-use crate::shell::{CommandExecutorInterface, Model};
+pub use crate::directory::*;
+pub use crate::environment::*;
+pub use crate::system::*;
+use crate::lexer::expand_word;
+use crate::shell::{wait_for, CommandExecutorInterface, CommandOutput, Model};
 
 /// Context about the environment in which this shell is being run. 
 pub struct Context {
   pub uname: String,
   pub shell: String,
   pub os: String,
+  pub system: SystemInfo,
   pub pwd: String,
+  pub dirs: DirectoryState,
+  pub env: Environment,
+  pub aliases: Aliases,
+  pub config: Config,
+
+  /// Supplementary details about the environment for the model's benefit, such as installed tools or the
+  /// contents of the working directory; refreshed before each model request
+  pub details: Vec<String>,
   pub history: CommandHistory
 }
 
@@ -25,6 +41,13 @@ get_current_working_dir () -> Result<String, Error>
   })
 }
 
+/// The directory holding nl-sh's own state, `~/.nl-sh`
+pub fn
+nl_sh_dir () -> Option<PathBuf>
+{
+  home::home_dir().map(|home| home.join(".nl-sh"))
+}
+
 /// Sanitizes stdout read from executed `Command`s. 
 pub fn 
 sanitize_stdout (stdout: &str) -> String
@@ -34,52 +57,153 @@ sanitize_stdout (stdout: &str) -> String
   re.replace_all(stdout.trim(), " ").into_owned()
 }
 
+/// Collects the hostname, kernel release and a `uname -smr`-style summary of the system, from a single
+/// call to `uname`.
+fn
+identify_host (shell: &str, executor: &dyn CommandExecutorInterface, env: &Environment) -> io::Result<(String, String, String)>
+{
+  // Fields are always printed in the order: kernel name, hostname, kernel release, machine
+  let uname_output = executor.execute(shell, "uname -snrm", env)?;
+  let fields: Vec<&str> = uname_output.stdout.split_whitespace().collect();
+
+  match fields.as_slice() {
+    [name, hostname, release, machine @ ..] => {
+      let uname = sanitize_stdout(&format!("{name} {release} {}", machine.join(" ")));
+      Ok((hostname.to_string(), release.to_string(), uname))
+    },
+    _ => Err(io::Error::other(format!("unexpected uname output: {}", uname_output.stdout)))
+  }
+}
+
+/// Computes the environment facts for this host from scratch, and caches them for future sessions. Facts are only
+/// cached once the operating system has been identified; otherwise the next session tries again.
+fn
+probe (shell: &str, hostname: &str, kernel: &str, uname: &str, executor: &dyn CommandExecutorInterface,
+  model: &dyn Model, env: &Environment) -> CachedContext
+{
+  let (system, os) = match SystemInfo::detect(shell, executor, env) {
+    Some(system) => {
+      let os = system.describe();
+      (system, Some(os))
+    },
+    None => (SystemInfo::default(), ask_model_for_os(uname, model))
+  };
+  let identified = os.is_some();
+
+  let facts = CachedContext {
+    hostname: hostname.to_string(),
+    kernel: kernel.to_string(),
+    uname: uname.to_string(),
+    shell: shell.to_string(),
+    os: os.unwrap_or_else(|| uname.to_string()),
+    system,
+  };
+
+  if identified {
+    if let Err(e) = facts.save() {
+      eprintln!("failed to cache environment context: {e}");
+    }
+  }
+
+  facts
+}
+
+/// Fallback for systems `SystemInfo` can't identify: asks the model to name the operating system variant and
+/// version from the `uname` output, and uses its reply as the description. Nothing the model says is run. Returns
+/// `None` if the model can't answer, in which case the `uname` output is the best description available.
+fn
+ask_model_for_os (uname: &str, model: &dyn Model) -> Option<String>
+{
+  match wait_for("identifying operating system", model.init_prompt(uname)) {
+    Ok(response) => Some(sanitize_stdout(&response)).filter(|os| !os.is_empty()),
+    Err(e) => {
+      eprintln!("failed to collect operating system details from model: {e}");
+      None
+    }
+  }
+}
+
 impl Context 
 {
-  /// Initializes a `Context` by issuing a preliminary request to the `Model` 
-  /// asking for the best next command to gather local OS and environment information, given
-  /// the content of a call to `uname`
-  pub fn init (args: &Args, executor: &dyn CommandExecutorInterface, model: &dyn Model) -> io::Result<Context> 
+  /// Initializes a `Context` by fingerprinting the local operating system and environment, given the
+  /// content of a call to `uname`. Only if the system can't be identified natively is the `Model` consulted,
+  /// asking it to describe the operating system from the `uname` output. The results are cached per host, and
+  /// reused on later launches until the kernel version changes.
+  pub fn init (args: &Args, config: Config, executor: &dyn CommandExecutorInterface, model: &dyn Model) -> io::Result<Context> 
   {
     let shell_path = match env::var("SHELL") {
       Ok(shell_path) => shell_path,
       Err(e) => panic!("failed to determine shell: {e}")
     };
   
-    let uname_output = executor.execute(shell_path.as_str(), "uname -smr")?;
-  
-    let os = &sanitize_stdout(uname_output.stdout.as_str());
-    let os_command = match model.init_prompt(os) {
-      Ok(response) => response,
-      Err(e) => panic!("Failed to initialize environment context due to model error: {e}")
+    let env = Environment::new();
+    let (hostname, kernel, uname) = identify_host(&shell_path, executor, &env)?;
+
+    let facts = match CachedContext::load(&hostname, &kernel, &shell_path) {
+      Some(cached) => cached,
+      None => probe(&shell_path, &hostname, &kernel, &uname, executor, model, &env)
     };
-  
-    let os_output = executor.execute(shell_path.as_str(), os_command.clone().as_str())?;
-    if os_output.success {
-      Ok(Context {
-        uname: sanitize_stdout(&os),
-        shell: shell_path.clone(),
-        os: sanitize_stdout(&os_output.stdout),
-        pwd: get_current_working_dir().unwrap(),
-        history: CommandHistory::init(shell_path.as_str(), !args.stateless)?
-      })
-    } else {
-      panic!("failed to collect {os_command} outputs: {}", os_output.stderr);
+
+    Ok(Context {
+      uname: facts.uname,
+      shell: shell_path.clone(),
+      os: facts.os,
+      system: facts.system,
+      pwd: get_current_working_dir().unwrap(),
+      dirs: DirectoryState::new(),
+      env,
+      aliases: Aliases::load(shell_path.as_str()),
+      config,
+      details: Vec::new(),
+      history: CommandHistory::init(shell_path.as_str(), !args.stateless)?
+    })
+  }
+
+  /// A `Context` for tests: a macOS host running zsh, in the given working directory, with default settings
+  #[cfg(test)]
+  pub fn for_test (pwd: &str) -> Context
+  {
+    Context {
+      uname: "Darwin 23.3.0 arm64".to_string(),
+      shell: "/bin/zsh".to_string(),
+      os: "macOS 14.3".to_string(),
+      system: SystemInfo::default(),
+      pwd: pwd.to_string(),
+      dirs: DirectoryState::new(),
+      env: Environment::new(),
+      aliases: Aliases::new(),
+      config: Config::new(),
+      details: Vec::new(),
+      history: CommandHistory::init("/bin/zsh", false).unwrap(),
     }
   }
 
+  /// Discards any cached environment facts and probes the system again
+  pub fn refresh (&mut self, executor: &dyn CommandExecutorInterface, model: &dyn Model) -> io::Result<()>
+  {
+    let (hostname, kernel, uname) = identify_host(&self.shell, executor, &self.env)?;
+    let facts = probe(&self.shell, &hostname, &kernel, &uname, executor, model, &self.env);
+
+    self.uname = facts.uname;
+    self.os = facts.os;
+    self.system = facts.system;
+
+    Ok(())
+  }
+
   /// Conditionally updates the given `Context`, depending on the nature of the sucessfullly-executed command string.
-  pub fn update (&mut self, cmd_input: &str) -> Result<(), Box<dyn std::error::Error>>
+  /// `expanded` is the command as it ran, with aliases expanded, which is what the context follows; `cmd_input` is the
+  /// command as typed, which is what's recorded in history.
+  pub fn update (&mut self, cmd_input: &str, expanded: &str) -> Result<(), Box<dyn std::error::Error>>
   {
-    let mut parts: Vec<&str> = cmd_input.split_whitespace().collect();
-    let cmd = parts.remove(0);
-
-    if cmd.to_owned().to_lowercase().eq("cd") && parts.len() >= 1 {
-      // If this was a change-directory command, set the current environment to
-      // cd's subsequent argument and update `context.pwd`
-      env::set_current_dir(parts.remove(0))?;
-      self.pwd = get_current_working_dir()?;
-    }
+    // Carry any exported variables and aliases defined by this command forward to later commands
+    self.env.follow(expanded);
+    self.aliases.follow(expanded);
+
+    // The command has already succeeded in the underlying shell, so follow along with any directory changes
+    // it made, such as the `cd build` in `cd build && make`. A failure here means the change wasn't one the
+    // shell actually made (for example `cd missing || true`), so there's nothing to follow.
+    let _ = self.follow_directory_changes(expanded);
 
     // Possibly update command history with this most recent command
     self.update_command(cmd_input)?;
@@ -87,6 +211,55 @@ impl Context
     Ok(())
   }
 
+  /// Runs a command made up solely of directory builtins (`cd`, `pushd`, `popd` and `dirs`) against this
+  /// `Context`. The underlying shell runs each command in a new process with an empty directory stack, so
+  /// these are handled here instead; the result is reported the same way as an executed command. As with `update`,
+  /// `expanded` is what's run and `cmd_input` what's recorded in history.
+  pub fn change_directory (&mut self, cmd_input: &str, expanded: &str) -> io::Result<CommandOutput>
+  {
+    match self.follow_directory_changes(expanded) {
+      Ok(stdout) => {
+        self.update_command(cmd_input)?;
+        Ok(CommandOutput::from_fields(true, 0, stdout, String::new()))
+      },
+      Err(e) => Ok(CommandOutput::from_fields(false, 1, String::new(), format!("{e}\n")))
+    }
+  }
+
+  /// Applies each directory builtin within `cmd_input` in turn, updating `pwd` and the process' working
+  /// directory to match. Returns whatever the builtins print, stopping at the first which fails.
+  fn follow_directory_changes (&mut self, cmd_input: &str) -> io::Result<String>
+  {
+    let mut stdout = String::new();
+
+    for words in directory_commands(cmd_input) {
+      let home = self.lookup_var("HOME").unwrap_or_default();
+      let args: Vec<String> = words.iter()
+        .map(|word| expand_word(word, &|name| self.lookup_var(name)))
+        .collect();
+
+      let (pwd, output) = self.dirs.run(&self.pwd, &home, &args)?;
+      env::set_current_dir(&pwd)?;
+      self.pwd = pwd;
+      stdout.push_str(&output);
+    }
+
+    Ok(stdout)
+  }
+
+  /// Looks up a variable for expanding command arguments, including the `PWD` and `OLDPWD` tracked here
+  /// and anything exported earlier in the session
+  fn lookup_var (&self, name: &str) -> Option<String>
+  {
+    match name {
+      "PWD" => Some(self.pwd.clone()),
+      "OLDPWD" => self.dirs.oldpwd.clone(),
+      "HOME" => self.env.get(name)
+        .or_else(|| home::home_dir().map(|dir| dir.to_string_lossy().into_owned())),
+      _ => self.env.get(name)
+    }
+  }
+
   pub fn update_command (&mut self, cmd: &str) -> io::Result<()>
   {
     self.history.maybe_append_command(cmd)
@@ -120,21 +293,26 @@ mod tests {
   #[test]
   fn test_context_update_cd_command() {
     // Setup - create a temporary directory and initialize Context
+    let original_dir = env::current_dir().unwrap();
     let temp_dir = tempfile::tempdir().unwrap();
-    let mut context = Context {
-      uname: "Darwin".to_string(),
-      shell: "/bin/zsh".to_string(),
-      os: "Darwin 23.3.0 arm64".to_string(),
-      pwd: "/home".to_string(),
-      history: CommandHistory::init("/bin/zsh", false).unwrap(),
-    };
+    let mut context = Context::for_test("/home");
 
     // Test - change directory to temp_dir
     let cmd_input = format!("cd {}", temp_dir.path().to_str().unwrap());
-    context.update(&cmd_input).unwrap();
+    context.update(&cmd_input, &cmd_input).unwrap();
 
     // This is a contains rather than an equality check because of the
     // usage of /private symlink for temp files on Mac OS
     assert!(context.pwd.contains(temp_dir.path().to_str().unwrap()));
+
+    // A `cd` within an alias is followed too
+    let nested = temp_dir.path().join("proj");
+    std::fs::create_dir(&nested).unwrap();
+    context.aliases.follow(&format!("alias proj='cd {}'", nested.to_str().unwrap()));
+    context.update("proj && true", &context.aliases.expand("proj && true")).unwrap();
+    assert!(context.pwd.ends_with("/proj"));
+
+    // Restore the working directory before the temporary directory is removed
+    env::set_current_dir(original_dir).unwrap();
   }
 }
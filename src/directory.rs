@@ -0,0 +1,274 @@
+use std::io::{self, Error};
+use std::path::{Component, Path, PathBuf};
+
+use crate::lexer::*;
+
+/// Shell builtins which change or report on the working directory. The underlying shell runs every
+/// command in a fresh process, so these are tracked by nl-sh itself rather than by the shell.
+static DIRECTORY_BUILTINS: &[&str] = &["cd", "pushd", "popd", "dirs"];
+
+/// Directory state tracked alongside the current working directory: the previous working directory
+/// used by `cd -`, and the directory stack maintained by `pushd` and `popd`.
+#[derive(Clone, Debug, Default)]
+pub struct DirectoryState {
+  pub oldpwd: Option<String>,
+
+  /// The directory stack, excluding the current working directory; the most recently pushed
+  /// directory is last
+  pub stack: Vec<String>,
+}
+
+/// Determines whether the command line consists only of directory builtins, such as `cd -` or
+/// `popd; pushd /tmp`. These are handled entirely within nl-sh rather than the underlying shell.
+pub fn
+is_directory_command (cmd: &str) -> bool
+{
+  let commands = split_commands(cmd);
+  !commands.is_empty() && commands.iter().all(|command| {
+    !command.subshell && command.words.first().is_some_and(|word| is_directory_builtin(&word.text))
+  })
+}
+
+/// Extracts the directory builtins from a command line which affect the current shell, in the order
+/// they'd be run; for example `cd build` from `cd build && make`. Words are returned as written, prior
+/// to any expansion.
+pub fn
+directory_commands (cmd: &str) -> Vec<Vec<String>>
+{
+  split_commands(cmd)
+    .into_iter()
+    .filter(|command| !command.subshell)
+    .filter(|command| command.words.first().is_some_and(|word| is_directory_builtin(&word.text)))
+    .map(|command| command.words.iter().map(|word| cmd[word.span.clone()].to_string()).collect())
+    .collect()
+}
+
+fn
+is_directory_builtin (word: &str) -> bool
+{
+  DIRECTORY_BUILTINS.contains(&word)
+}
+
+/// Resolves `path` against the directory `pwd`, normalizing `.` and `..` components without following
+/// symlinks; the same way a shell tracks its logical working directory.
+pub fn
+resolve_path (pwd: &str, path: &str) -> String
+{
+  let mut resolved = PathBuf::new();
+  for component in Path::new(pwd).join(path).components() {
+    match component {
+      Component::CurDir => {},
+      Component::ParentDir => { resolved.pop(); },
+      c => resolved.push(c.as_os_str())
+    }
+  }
+
+  resolved.to_string_lossy().into_owned()
+}
+
+/// Abbreviates a leading home directory to `~`, as `dirs` and `pushd` do when printing the stack
+pub fn
+abbreviate_home (path: &str, home: &str) -> String
+{
+  if home.is_empty() {
+    return path.to_string();
+  }
+
+  match path.strip_prefix(home) {
+    Some("") => "~".to_string(),
+    Some(rest) if rest.starts_with('/') => format!("~{rest}"),
+    _ => path.to_string()
+  }
+}
+
+impl DirectoryState
+{
+  pub fn new () -> Self
+  {
+    DirectoryState { oldpwd: None, stack: Vec::new() }
+  }
+
+  /// Runs a single, already-expanded directory builtin such as `["cd", "/tmp"]` given the current
+  /// working directory `pwd`. Returns the new working directory along with anything the builtin
+  /// prints, or an error in the same form a shell would report it.
+  pub fn run (&mut self, pwd: &str, home: &str, args: &[String]) -> io::Result<(String, String)>
+  {
+    let (builtin, args) = match args.split_first() {
+      Some((builtin, args)) => (builtin.as_str(), args),
+      None => return Ok((pwd.to_string(), String::new()))
+    };
+
+    // Options such as `cd -P` only affect symlink handling, which isn't tracked here
+    let args: Vec<&str> = args.iter()
+      .map(|arg| arg.as_str())
+      .filter(|arg| *arg == "-" || !arg.starts_with('-'))
+      .collect();
+
+    match (builtin, args.first()) {
+      ("cd", None) => self.change(pwd, home, builtin).map(|dir| (dir, String::new())),
+      ("cd", Some(&"-")) => {
+        let oldpwd = self.oldpwd.clone().ok_or_else(|| failure("cd", "OLDPWD not set"))?;
+        let dir = self.change(pwd, &oldpwd, builtin)?;
+        Ok((dir.clone(), format!("{dir}\n")))
+      },
+      ("cd", Some(dir)) => self.change(pwd, dir, builtin).map(|dir| (dir, String::new())),
+      ("pushd", None) => {
+        let top = self.stack.pop().ok_or_else(|| failure("pushd", "no other directory"))?;
+        match self.change(pwd, &top, builtin) {
+          Ok(dir) => {
+            self.stack.push(pwd.to_string());
+            Ok((dir.clone(), self.listing(&dir, home)))
+          },
+          Err(e) => {
+            self.stack.push(top);
+            Err(e)
+          }
+        }
+      },
+      ("pushd", Some(dir)) => {
+        let dir = self.change(pwd, dir, builtin)?;
+        self.stack.push(pwd.to_string());
+        Ok((dir.clone(), self.listing(&dir, home)))
+      },
+      ("popd", _) => {
+        let top = self.stack.last().cloned().ok_or_else(|| failure("popd", "directory stack empty"))?;
+        let dir = self.change(pwd, &top, builtin)?;
+        self.stack.pop();
+        Ok((dir.clone(), self.listing(&dir, home)))
+      },
+      ("dirs", _) => Ok((pwd.to_string(), self.listing(pwd, home))),
+      _ => Err(failure(builtin, "unsupported directory command"))
+    }
+  }
+
+  /// Moves from `pwd` to `dir`, recording `pwd` as the previous directory
+  fn change (&mut self, pwd: &str, dir: &str, builtin: &str) -> io::Result<String>
+  {
+    let resolved = resolve_path(pwd, dir);
+    if !Path::new(&resolved).is_dir() {
+      return Err(failure(builtin, &format!("no such file or directory: {dir}")));
+    }
+
+    self.oldpwd = Some(pwd.to_string());
+    Ok(resolved)
+  }
+
+  /// Formats the directory stack the way `dirs` prints it; the current directory first, followed by
+  /// the stack from most to least recently pushed
+  fn listing (&self, pwd: &str, home: &str) -> String
+  {
+    let mut dirs = vec![abbreviate_home(pwd, home)];
+    dirs.extend(self.stack.iter().rev().map(|dir| abbreviate_home(dir, home)));
+    format!("{}\n", dirs.join(" "))
+  }
+}
+
+fn
+failure (builtin: &str, message: &str) -> Error
+{
+  Error::other(format!("{builtin}: {message}"))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn args (input: &str) -> Vec<String>
+  {
+    input.split_whitespace().map(|s| s.to_string()).collect()
+  }
+
+  fn path (dir: &tempfile::TempDir, name: &str) -> String
+  {
+    let p = dir.path().join(name);
+    std::fs::create_dir_all(&p).unwrap();
+    p.to_str().unwrap().to_string()
+  }
+
+  #[test]
+  fn test_is_directory_command () {
+    assert!(is_directory_command("cd"));
+    assert!(is_directory_command("cd ~/src"));
+    assert!(is_directory_command("popd && pushd /tmp"));
+    assert!(!is_directory_command("cd foo && make"));
+    assert!(!is_directory_command("(cd foo)"));
+    assert!(!is_directory_command("ls"));
+    assert!(!is_directory_command(""));
+  }
+
+  #[test]
+  fn test_directory_commands () {
+    assert_eq!(directory_commands("cd 'my dir' && make"), vec![vec!["cd".to_string(), "'my dir'".to_string()]]);
+    assert_eq!(directory_commands("cd src; ls | cd /tmp"), vec![vec!["cd".to_string(), "src".to_string()]]);
+    assert!(directory_commands("make").is_empty());
+  }
+
+  #[test]
+  fn test_resolve_path () {
+    assert_eq!(resolve_path("/home/mike", "src"), "/home/mike/src");
+    assert_eq!(resolve_path("/home/mike", "../other/./x"), "/home/other/x");
+    assert_eq!(resolve_path("/home/mike", "/tmp"), "/tmp");
+    assert_eq!(resolve_path("/", ".."), "/");
+  }
+
+  #[test]
+  fn test_cd () {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let home = path(&temp_dir, "home");
+    let src = path(&temp_dir, "home/src");
+
+    let mut dirs = DirectoryState::new();
+    let (pwd, _) = dirs.run(&home, &home, &args("cd src")).unwrap();
+    assert_eq!(pwd, src);
+    assert_eq!(dirs.oldpwd, Some(home.clone()));
+
+    // Bare `cd` returns home
+    let (pwd, _) = dirs.run(&pwd, &home, &args("cd")).unwrap();
+    assert_eq!(pwd, home);
+
+    // `cd -` returns to the previous directory and prints it
+    let (pwd, output) = dirs.run(&pwd, &home, &args("cd -")).unwrap();
+    assert_eq!(pwd, src);
+    assert_eq!(output, format!("{src}\n"));
+
+    assert!(dirs.run(&pwd, &home, &args("cd missing")).is_err());
+  }
+
+  #[test]
+  fn test_cd_without_oldpwd () {
+    let mut dirs = DirectoryState::new();
+    let err = dirs.run("/", "/", &args("cd -")).unwrap_err();
+    assert_eq!(err.to_string(), "cd: OLDPWD not set");
+  }
+
+  #[test]
+  fn test_pushd_popd () {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let home = path(&temp_dir, "home");
+    let a = path(&temp_dir, "home/a");
+    let b = path(&temp_dir, "b");
+
+    let mut dirs = DirectoryState::new();
+    let (pwd, output) = dirs.run(&home, &home, &args("pushd a")).unwrap();
+    assert_eq!(pwd, a);
+    assert_eq!(output, "~/a ~\n");
+
+    let (pwd, _) = dirs.run(&pwd, &home, &[String::from("pushd"), b.clone()]).unwrap();
+    assert_eq!(pwd, b);
+    assert_eq!(dirs.stack, vec![home.clone(), a.clone()]);
+
+    // `pushd` with no arguments swaps the top two directories
+    let (pwd, _) = dirs.run(&pwd, &home, &args("pushd")).unwrap();
+    assert_eq!(pwd, a);
+    assert_eq!(dirs.stack, vec![home.clone(), b.clone()]);
+
+    let (pwd, _) = dirs.run(&pwd, &home, &args("popd")).unwrap();
+    assert_eq!(pwd, b);
+    let (pwd, output) = dirs.run(&pwd, &home, &args("popd")).unwrap();
+    assert_eq!(pwd, home);
+    assert_eq!(output, "~\n");
+
+    let err = dirs.run(&pwd, &home, &args("popd")).unwrap_err();
+    assert_eq!(err.to_string(), "popd: directory stack empty");
+  }
+}
@@ -0,0 +1,308 @@
+use std::collections::{HashMap, HashSet};
+use std::env;
+use std::fs;
+use std::process;
+use std::time::Duration;
+
+use crate::command::output_within;
+use crate::lexer::*;
+
+/// Rc files which commonly define aliases, relative to the user's home directory. Read only when the
+/// aliases can't be collected from an interactive instance of the user's shell.
+static ALIAS_RC_FILES: &[&str] = &[".bashrc", ".bash_aliases", ".bash_profile", ".zshrc", ".kshrc", ".tcshrc", ".aliases"];
+
+/// Limit on how long the user's shell may take to start up and list its aliases
+const ALIAS_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// Environment variables exported or unset within nl-sh. Each command runs in a new instance of the
+/// underlying shell, so these are applied to every spawned process to carry them between commands.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Environment {
+  pub vars: HashMap<String, String>,
+  pub unset: HashSet<String>,
+}
+
+/// Shell aliases, such as `ll='ls -l'`. Non-interactive shells don't expand aliases, so nl-sh expands
+/// them itself before handing commands to the underlying shell.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Aliases {
+  pub table: HashMap<String, String>,
+}
+
+impl Environment
+{
+  pub fn new () -> Self
+  {
+    Environment { vars: HashMap::new(), unset: HashSet::new() }
+  }
+
+  /// The value of a variable as seen by commands run through nl-sh
+  pub fn get (&self, name: &str) -> Option<String>
+  {
+    match self.vars.get(name) {
+      Some(value) => Some(value.clone()),
+      None if self.unset.contains(name) => None,
+      None => env::var(name).ok()
+    }
+  }
+
+  /// Applies the tracked variables to a `Command` about to be spawned
+  pub fn apply (&self, command: &mut process::Command)
+  {
+    for name in &self.unset {
+      command.env_remove(name);
+    }
+
+    command.envs(&self.vars);
+  }
+
+  /// Follows any `export` or `unset` builtins within a successfully-executed command line
+  pub fn follow (&mut self, cmd: &str)
+  {
+    for command in split_commands(cmd).into_iter().filter(|command| !command.subshell) {
+      let (builtin, args) = match command.words.split_first() {
+        Some((builtin, args)) => (builtin.text.as_str(), args),
+        None => continue
+      };
+
+      for arg in args.iter().filter(|arg| !arg.text.starts_with('-')) {
+        match builtin {
+          "export" => {
+            // Values are expanded against the environment as it stood before this command,
+            // so that `export PATH=$PATH:~/bin` works as expected
+            let raw = &cmd[arg.span.clone()];
+            if let Some((name, value)) = raw.split_once('=') {
+              if is_assignment(raw) {
+                let value = expand_word(value, &|var| self.get(var));
+                self.unset.remove(name);
+                self.vars.insert(name.to_string(), value);
+              }
+            }
+          },
+          "unset" => {
+            self.vars.remove(&arg.text);
+            self.unset.insert(arg.text.clone());
+          },
+          _ => break
+        }
+      }
+    }
+  }
+}
+
+impl Aliases
+{
+  pub fn new () -> Self
+  {
+    Aliases { table: HashMap::new() }
+  }
+
+  /// Collects the user's aliases by asking an interactive instance of their shell to list them, falling
+  /// back to scanning common rc files for `alias` definitions. The shell runs detached from the terminal and
+  /// is abandoned if it doesn't answer in time, as an rc file may start `tmux`, prompt for input and so on.
+  pub fn load (shell: &str) -> Self
+  {
+    let listed = output_within(process::Command::new(shell).args(["-i", "-c", "alias"]), ALIAS_TIMEOUT)
+      .filter(|output| output.status.success())
+      .map(|output| parse_alias_listing(&String::from_utf8_lossy(&output.stdout)))
+      .unwrap_or_default();
+
+    if !listed.is_empty() {
+      return Aliases { table: listed };
+    }
+
+    let mut aliases = Aliases::new();
+    if let Some(home) = home::home_dir() {
+      for rc_file in ALIAS_RC_FILES {
+        if let Ok(contents) = fs::read_to_string(home.join(rc_file)) {
+          for line in contents.lines().filter(|line| line.trim_start().starts_with("alias ")) {
+            aliases.follow(line);
+          }
+        }
+      }
+    }
+
+    aliases
+  }
+
+  pub fn contains (&self, name: &str) -> bool
+  {
+    self.table.contains_key(name)
+  }
+
+  /// Follows any `alias` or `unalias` builtins within a successfully-executed command line
+  pub fn follow (&mut self, cmd: &str)
+  {
+    for command in split_commands(cmd).into_iter().filter(|command| !command.subshell) {
+      let (builtin, args) = match command.words.split_first() {
+        Some((builtin, args)) => (builtin.text.as_str(), args),
+        None => continue
+      };
+
+      match builtin {
+        "alias" => {
+          for arg in args {
+            if let Some((name, value)) = arg.text.split_once('=') {
+              self.table.insert(name.to_string(), value.to_string());
+            }
+          }
+        },
+        "unalias" if args.iter().any(|arg| arg.text == "-a") => self.table.clear(),
+        "unalias" => {
+          for arg in args {
+            self.table.remove(&arg.text);
+          }
+        },
+        _ => {}
+      }
+    }
+  }
+
+  /// Expands aliases in the command position of each simple command in `cmd`. As in the shell, an alias
+  /// whose value begins with another alias is expanded again, but never recursively into itself; and a
+  /// quoted command name is never expanded.
+  pub fn expand (&self, cmd: &str) -> String
+  {
+    let mut expanded = cmd.to_string();
+
+    // Replace from the end of the line so that earlier spans remain valid
+    for command in split_commands(cmd).iter().rev() {
+      let name = match command.name() {
+        Some(name) if !name.quoted => name,
+        _ => continue
+      };
+
+      if let Some(value) = self.resolve(&name.text) {
+        expanded.replace_range(name.span.clone(), &value);
+      }
+    }
+
+    expanded
+  }
+
+  /// Resolves an alias name to its fully-expanded value, if it's an alias at all
+  fn resolve (&self, name: &str) -> Option<String>
+  {
+    let mut seen = vec![name.to_string()];
+    let mut value = self.table.get(name)?.clone();
+
+    loop {
+      let first = value.split_whitespace().next().unwrap_or("").to_string();
+      match self.table.get(&first) {
+        Some(next) if !seen.contains(&first) => {
+          value = value.replacen(&first, next, 1);
+          seen.push(first);
+        },
+        _ => return Some(value)
+      }
+    }
+  }
+}
+
+/// Parses the output of the `alias` builtin, which differs between shells:
+///
+///   bash:  alias ll='ls -l'
+///   zsh:   ll='ls -l'
+///   tcsh:  ll      (ls -l)
+fn
+parse_alias_listing (listing: &str) -> HashMap<String, String>
+{
+  let mut table = HashMap::new();
+
+  for line in listing.lines() {
+    let line = line.strip_prefix("alias ").unwrap_or(line);
+    let definition = match (line.split_once('='), line.split_once('\t')) {
+      (Some((name, value)), _) if !name.contains(char::is_whitespace) => {
+        // The value is quoted for re-use as shell input; unquote it the same way the shell would
+        let value = tokenize(value).into_iter().map(|token| token.text).collect::<Vec<String>>().join(" ");
+        Some((name, value))
+      },
+      (_, Some((name, value))) => {
+        let value = value.trim();
+        let value = value.strip_prefix('(').and_then(|v| v.strip_suffix(')')).unwrap_or(value);
+        Some((name, value.to_string()))
+      },
+      _ => None
+    };
+
+    if let Some((name, value)) = definition {
+      table.insert(name.to_string(), value);
+    }
+  }
+
+  table
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_follow_export_and_unset () {
+    let mut env = Environment::new();
+    env.follow("export EDITOR=vim NAME='nl sh' && make");
+    assert_eq!(env.get("EDITOR"), Some("vim".to_string()));
+    assert_eq!(env.get("NAME"), Some("nl sh".to_string()));
+
+    env.follow("export BASE=/opt; export BIN=$BASE/bin");
+    assert_eq!(env.get("BIN"), Some("/opt/bin".to_string()));
+
+    env.follow("unset EDITOR");
+    assert_eq!(env.get("EDITOR"), None);
+    assert!(env.unset.contains("EDITOR"));
+
+    // Exports within a subshell don't persist
+    env.follow("(export LOST=1)");
+    assert_eq!(env.get("LOST"), None);
+  }
+
+  #[test]
+  fn test_apply () {
+    let mut env = Environment::new();
+    env.follow("export NL_SH_TEST_VAR=hello");
+
+    let mut command = process::Command::new("/bin/sh");
+    command.arg("-c").arg("echo $NL_SH_TEST_VAR");
+    env.apply(&mut command);
+
+    let output = command.output().unwrap();
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "hello\n");
+  }
+
+  #[test]
+  fn test_follow_alias () {
+    let mut aliases = Aliases::new();
+    aliases.follow("alias ll='ls -l' la=\"ls -a\"");
+    assert_eq!(aliases.table.get("ll"), Some(&"ls -l".to_string()));
+    assert_eq!(aliases.table.get("la"), Some(&"ls -a".to_string()));
+
+    aliases.follow("unalias la");
+    assert!(!aliases.contains("la"));
+  }
+
+  #[test]
+  fn test_expand () {
+    let mut aliases = Aliases::new();
+    aliases.follow("alias ll='ls -l' ls='ls -G' g=git");
+
+    assert_eq!(aliases.expand("ll /tmp"), "ls -G -l /tmp");
+    assert_eq!(aliases.expand("g status && ll"), "git status && ls -G -l");
+    assert_eq!(aliases.expand("echo ll"), "echo ll");
+    assert_eq!(aliases.expand("'ll'"), "'ll'");
+    assert_eq!(aliases.expand("FOO=1 g log"), "FOO=1 git log");
+  }
+
+  #[test]
+  fn test_parse_alias_listing () {
+    let bash = parse_alias_listing("alias ll='ls -alF'\nalias gs='git status'\n");
+    assert_eq!(bash.get("ll"), Some(&"ls -alF".to_string()));
+    assert_eq!(bash.get("gs"), Some(&"git status".to_string()));
+
+    let zsh = parse_alias_listing("ll='ls -alF'\nrun-help=man\n");
+    assert_eq!(zsh.get("ll"), Some(&"ls -alF".to_string()));
+    assert_eq!(zsh.get("run-help"), Some(&"man".to_string()));
+
+    let tcsh = parse_alias_listing("ll\t(ls -alF)\n");
+    assert_eq!(tcsh.get("ll"), Some(&"ls -alF".to_string()));
+  }
+}
@@ -0,0 +1,133 @@
+use std::collections::HashMap;
+use std::env;
+use std::io::{self, IsTerminal};
+
+use crate::lexer::{is_assignment, split_commands, tokenize, TokenKind};
+use crate::risk::{command_start, WRAPPERS};
+
+/// ANSI escape sequences used to colour output
+pub const RESET: &str = "\x1b[0m";
+pub const BOLD_GREEN: &str = "\x1b[1;32m";
+pub const BOLD_RED: &str = "\x1b[1;31m";
+pub const BOLD_YELLOW: &str = "\x1b[1;33m";
+pub const CYAN: &str = "\x1b[36m";
+pub const BLUE: &str = "\x1b[34m";
+pub const MAGENTA: &str = "\x1b[35m";
+pub const DIM: &str = "\x1b[2m";
+pub const DIM_ITALIC: &str = "\x1b[2;3m";
+
+/// Commands which run another command with elevated privileges; highlighted as a warning
+pub static PRIVILEGED: &[&str] = &["sudo", "doas", "su", "pkexec"];
+
+/// Whether to colour output: only on a terminal, and never when the user has set `NO_COLOR`
+pub fn
+colors_enabled () -> bool
+{
+  env::var_os("NO_COLOR").is_none() && io::stdout().is_terminal()
+}
+
+/// Wraps text in an ANSI colour sequence
+pub fn
+paint (text: &str, color: &str) -> String
+{
+  format!("{color}{text}{RESET}")
+}
+
+/// Highlights a command line: command names in green, or in red when `exists` can't find them; flags, quoted strings,
+/// operators and redirections each in their own colour; and `sudo` in a warning colour. Spacing and quoting are
+/// kept exactly as written.
+pub fn
+highlight_command (input: &str, exists: &dyn Fn(&str) -> bool) -> String
+{
+  // The words of each simple command up to and including the command it really runs, by where they start; as in
+  // `sudo -u root rm`, where only `rm` and `sudo` are commands
+  let mut leading: HashMap<usize, bool> = HashMap::new();
+  for simple in split_commands(input) {
+    let words: Vec<&str> = simple.words.iter().map(|word| word.text.as_str()).collect();
+    let (start, _) = command_start(&words);
+    for (i, word) in simple.words.iter().enumerate().take(start + 1) {
+      leading.insert(word.span.start, i == start);
+    }
+  }
+
+  let mut highlighted = String::with_capacity(input.len() * 2);
+  let mut position = 0;
+
+  for token in tokenize(input) {
+    // Whitespace and comments between tokens are kept as-is
+    highlighted.push_str(&input[position..token.span.start]);
+    let text = &input[token.span.clone()];
+    position = token.span.end;
+
+    let command = |name: &str| if exists(name) { BOLD_GREEN } else { BOLD_RED };
+    let color = match (token.kind, leading.get(&token.span.start)) {
+      (TokenKind::Operator | TokenKind::Redirect, _) => MAGENTA,
+      (TokenKind::Word, Some(true)) => command(&token.text),
+      // Within a prefix, such as the `-u root` of `sudo -u root rm`
+      (TokenKind::Word, Some(false)) if PRIVILEGED.contains(&token.text.as_str()) => BOLD_YELLOW,
+      (TokenKind::Word, Some(false)) if WRAPPERS.contains(&token.text.as_str()) => command(&token.text),
+      (TokenKind::Word, Some(false)) if is_assignment(&token.text) => "",
+      (TokenKind::Word, _) if token.quoted => BLUE,
+      (TokenKind::Word, _) if token.text.starts_with('-') => CYAN,
+      (TokenKind::Word, _) => ""
+    };
+
+    if color.is_empty() {
+      highlighted.push_str(text);
+    } else {
+      highlighted.push_str(&paint(text, color));
+    }
+  }
+
+  highlighted.push_str(&input[position..]);
+  highlighted
+}
+
+/// Highlights a line typed at the prompt according to how nl-sh will treat it: as a command, or dimmed as a natural
+/// language request for the model
+pub fn
+highlight_input (input: &str, is_command: bool, exists: &dyn Fn(&str) -> bool) -> String
+{
+  if is_command {
+    highlight_command(input, exists)
+  } else {
+    paint(input, DIM_ITALIC)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn exists (name: &str) -> bool
+  {
+    ["ls", "grep", "rm"].contains(&name)
+  }
+
+  #[test]
+  fn test_highlight_command () {
+    assert_eq!(highlight_command("ls  -la | grep 'a b' > out", &exists), format!("{}  {} {} {} {} {} out",
+      paint("ls", BOLD_GREEN), paint("-la", CYAN), paint("|", MAGENTA), paint("grep", BOLD_GREEN),
+      paint("'a b'", BLUE), paint(">", MAGENTA)));
+  }
+
+  #[test]
+  fn test_highlight_missing_and_privileged () {
+    assert_eq!(highlight_command("sudo FOO=1 rm x && gti status", &exists), format!("{} FOO=1 {} x {} {} status",
+      paint("sudo", BOLD_YELLOW), paint("rm", BOLD_GREEN), paint("&&", MAGENTA), paint("gti", BOLD_RED)));
+  }
+
+  #[test]
+  fn test_highlight_prefix_options () {
+    assert_eq!(highlight_command("sudo -u root rm -rf x", &exists), format!("{} {} root {} {} x",
+      paint("sudo", BOLD_YELLOW), paint("-u", CYAN), paint("rm", BOLD_GREEN), paint("-rf", CYAN)));
+    assert_eq!(highlight_command("ls | xargs -n 1 grep a", &exists), format!("{} {} {} {} 1 {} a",
+      paint("ls", BOLD_GREEN), paint("|", MAGENTA), paint("xargs", BOLD_RED), paint("-n", CYAN), paint("grep", BOLD_GREEN)));
+  }
+
+  #[test]
+  fn test_highlight_input () {
+    assert_eq!(highlight_input("list my files", false, &exists), paint("list my files", DIM_ITALIC));
+    assert_eq!(highlight_input("ls # comment", true, &exists), format!("{} # comment", paint("ls", BOLD_GREEN)));
+  }
+}
@@ -0,0 +1,473 @@
+use std::iter::Peekable;
+use std::ops::Range;
+use std::str::CharIndices;
+
+/// The kinds of tokens produced by `tokenize`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TokenKind {
+  /// A word, such as a command name, argument or path
+  Word,
+  /// A list or pipeline control operator: `&&`, `||`, `;`, `&`, `|`, `(`, `)` or a newline
+  Operator,
+  /// An I/O redirection such as `>`, `>>`, `2>` or `<`
+  Redirect,
+}
+
+/// A single token from a command line. `text` holds the token with quotes and escapes removed, while
+/// `span` is the byte range of the token as written in the original input.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Token {
+  pub kind: TokenKind,
+  pub text: String,
+  pub span: Range<usize>,
+  pub quoted: bool,
+}
+
+/// A simple command extracted from a command line, such as `make` from `cd src && make`. Redirections
+/// and their targets are dropped from `words`.
+#[derive(Clone, Debug)]
+pub struct SimpleCommand {
+  pub words: Vec<Token>,
+
+  /// Whether the command runs in a child of the shell rather than the shell itself; as is the case
+  /// for parts of a pipeline, background jobs and anything within `( ... )`
+  pub subshell: bool,
+}
+
+impl SimpleCommand {
+  /// The command name, skipping any leading `NAME=value` variable assignments
+  pub fn name (&self) -> Option<&Token>
+  {
+    self.words.iter().find(|word| !is_assignment(&word.text))
+  }
+}
+
+/// Whether a word has the form of a shell variable assignment, e.g. `PATH=/usr/bin`
+pub fn
+is_assignment (word: &str) -> bool
+{
+  match word.split_once('=') {
+    Some((name, _)) => !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+      && !name.starts_with(|c: char| c.is_ascii_digit()),
+    None => false
+  }
+}
+
+/// Splits a command line into words and operators following POSIX shell quoting rules. Single quotes,
+/// double quotes and backslash escapes are honoured, and `$(...)`, `${...}` and backtick substitutions
+/// are kept intact within the word containing them. Comments are dropped.
+pub fn
+tokenize (input: &str) -> Vec<Token>
+{
+  let mut tokens = Vec::new();
+  let mut chars = input.char_indices().peekable();
+
+  let mut word = String::new();
+  let mut start: Option<usize> = None;
+  let mut end = 0;
+  let mut quoted = false;
+
+  while let Some((i, c)) = chars.next() {
+    match c {
+      '\'' => {
+        start.get_or_insert(i);
+        quoted = true;
+        end = input.len();
+        for (j, ch) in chars.by_ref() {
+          if ch == '\'' {
+            end = j + 1;
+            break;
+          }
+          word.push(ch);
+        }
+      },
+      '"' => {
+        start.get_or_insert(i);
+        quoted = true;
+        end = input.len();
+        while let Some((j, ch)) = chars.next() {
+          match ch {
+            '"' => {
+              end = j + 1;
+              break;
+            },
+            '\\' => {
+              // Within double quotes, a backslash only escapes characters which are otherwise special
+              match chars.peek() {
+                Some(&(_, next)) if matches!(next, '$' | '`' | '"' | '\\' | '\n') => {
+                  chars.next();
+                  if next != '\n' {
+                    word.push(next);
+                  }
+                },
+                _ => word.push(ch)
+              }
+            },
+            _ => word.push(ch)
+          }
+        }
+      },
+      '\\' => {
+        start.get_or_insert(i);
+        end = i + 1;
+        if let Some((j, next)) = chars.next() {
+          end = j + next.len_utf8();
+          if next != '\n' {
+            quoted = true;
+            word.push(next);
+          }
+        }
+      },
+      '$' if matches!(chars.peek(), Some((_, '(')) | Some((_, '{'))) => {
+        // Command and parameter substitutions are kept whole, including any operators they contain
+        start.get_or_insert(i);
+        word.push(c);
+        end = consume_balanced(&mut chars, &mut word, input.len());
+      },
+      '`' => {
+        start.get_or_insert(i);
+        word.push(c);
+        end = input.len();
+        for (j, ch) in chars.by_ref() {
+          word.push(ch);
+          if ch == '`' {
+            end = j + 1;
+            break;
+          }
+        }
+      },
+      '#' if start.is_none() => {
+        // A comment runs until the end of the line
+        while let Some(&(_, ch)) = chars.peek() {
+          if ch == '\n' {
+            break;
+          }
+          chars.next();
+        }
+      },
+      '<' | '>' if start.is_some() && !quoted && word.chars().all(|ch| ch.is_ascii_digit()) => {
+        // A file descriptor number immediately preceding a redirection, as in `2>/dev/null`
+        let redirect_start = start.take().unwrap();
+        let mut token = operator(&mut chars, c, i, redirect_start);
+        token.text.insert_str(0, &std::mem::take(&mut word));
+        tokens.push(token);
+      },
+      '\n' | ';' | '&' | '|' | '(' | ')' | '<' | '>' => {
+        flush(&mut tokens, &mut word, &mut start, end, &mut quoted);
+        tokens.push(operator(&mut chars, c, i, i));
+      },
+      c if c.is_whitespace() => {
+        flush(&mut tokens, &mut word, &mut start, end, &mut quoted);
+      },
+      _ => {
+        start.get_or_insert(i);
+        end = i + c.len_utf8();
+        word.push(c);
+      }
+    }
+  }
+
+  flush(&mut tokens, &mut word, &mut start, end, &mut quoted);
+  tokens
+}
+
+/// Splits a command line into the simple commands it's composed of, in the order they appear
+pub fn
+split_commands (input: &str) -> Vec<SimpleCommand>
+{
+  let mut commands = Vec::new();
+  let mut current = SimpleCommand { words: Vec::new(), subshell: false };
+  let mut depth = 0;
+  let mut redirect_target = false;
+
+  for token in tokenize(input) {
+    match token.kind {
+      TokenKind::Word => {
+        if redirect_target {
+          redirect_target = false;
+        } else {
+          current.words.push(token);
+        }
+      },
+      TokenKind::Redirect => {
+        redirect_target = true;
+      },
+      TokenKind::Operator => {
+        redirect_target = false;
+        let piped = token.text == "|" || token.text == "&";
+        let next_subshell = match token.text.as_str() {
+          "(" => { depth += 1; true },
+          ")" => { depth -= 1; depth > 0 },
+          "|" => true,
+          _ => depth > 0
+        };
+
+        current.subshell |= piped || depth > 0;
+        if !current.words.is_empty() {
+          commands.push(current);
+        }
+
+        current = SimpleCommand { words: Vec::new(), subshell: next_subshell };
+      }
+    }
+  }
+
+  if !current.words.is_empty() {
+    commands.push(current);
+  }
+
+  commands
+}
+
+/// Expands a single word as written on the command line: a leading unquoted `~` becomes the user's home
+/// directory, `$VAR` and `${VAR}` are substituted outside of single quotes, and quotes and escapes are
+/// removed. `lookup` provides variable values; unknown variables expand to nothing.
+pub fn
+expand_word (raw: &str, lookup: &dyn Fn(&str) -> Option<String>) -> String
+{
+  let mut result = String::new();
+  let mut chars = raw.chars().peekable();
+  let mut in_double = false;
+
+  if raw == "~" || raw.starts_with("~/") {
+    result.push_str(&lookup("HOME").unwrap_or_default());
+    chars.next();
+  }
+
+  while let Some(c) = chars.next() {
+    match c {
+      '\'' if !in_double => {
+        for ch in chars.by_ref() {
+          if ch == '\'' {
+            break;
+          }
+          result.push(ch);
+        }
+      },
+      '"' => in_double = !in_double,
+      '\\' => {
+        if let Some(&next) = chars.peek() {
+          if !in_double || matches!(next, '$' | '`' | '"' | '\\') {
+            chars.next();
+            result.push(next);
+          } else {
+            result.push(c);
+          }
+        }
+      },
+      '$' => {
+        let mut name = String::new();
+        if chars.peek() == Some(&'{') {
+          chars.next();
+          for ch in chars.by_ref() {
+            if ch == '}' {
+              break;
+            }
+            name.push(ch);
+          }
+        } else {
+          while let Some(&ch) = chars.peek() {
+            if ch.is_ascii_alphanumeric() || ch == '_' {
+              name.push(ch);
+              chars.next();
+            } else {
+              break;
+            }
+          }
+        }
+
+        if name.is_empty() {
+          result.push(c);
+        } else {
+          result.push_str(&lookup(&name).unwrap_or_default());
+        }
+      },
+      _ => result.push(c)
+    }
+  }
+
+  result
+}
+
+fn
+flush (tokens: &mut Vec<Token>, word: &mut String, start: &mut Option<usize>, end: usize, quoted: &mut bool)
+{
+  if let Some(s) = start.take() {
+    tokens.push(Token { kind: TokenKind::Word, text: std::mem::take(word), span: s..end, quoted: *quoted });
+  }
+  *quoted = false;
+}
+
+/// Reads the remainder of an operator or redirection beginning with `c` at index `i`. The token's span
+/// begins at `start`, which precedes `i` when a file descriptor number was folded into a redirection.
+fn
+operator (chars: &mut Peekable<CharIndices>, c: char, i: usize, start: usize) -> Token
+{
+  let mut text = c.to_string();
+  let mut end = i + 1;
+  let mut kind = TokenKind::Operator;
+
+  match c {
+    '&' => match chars.peek() {
+      Some((_, '&')) => take(chars, &mut text, &mut end),
+      Some((_, '>')) => {
+        kind = TokenKind::Redirect;
+        take(chars, &mut text, &mut end);
+        if let Some((_, '>')) = chars.peek() {
+          take(chars, &mut text, &mut end);
+        }
+      },
+      _ => {}
+    },
+    '|' => if let Some((_, '|')) = chars.peek() {
+      take(chars, &mut text, &mut end);
+    },
+    ';' => if let Some((_, ';')) = chars.peek() {
+      take(chars, &mut text, &mut end);
+    },
+    '<' | '>' => {
+      kind = TokenKind::Redirect;
+      if let Some(&(_, next)) = chars.peek() {
+        if next == c || next == '&' || (c == '>' && next == '|') || (c == '<' && next == '>') {
+          take(chars, &mut text, &mut end);
+        }
+      }
+    },
+    _ => {}
+  }
+
+  Token { kind, text, span: start..end, quoted: false }
+}
+
+/// Appends the next character to an operator being read
+fn
+take (chars: &mut Peekable<CharIndices>, text: &mut String, end: &mut usize)
+{
+  if let Some((j, ch)) = chars.next() {
+    text.push(ch);
+    *end = j + ch.len_utf8();
+  }
+}
+
+/// Consumes a `$(...)` or `${...}` substitution into `word`, returning the end of its span
+fn
+consume_balanced (chars: &mut Peekable<CharIndices>, word: &mut String, len: usize) -> usize
+{
+  let (open, close) = match chars.peek() {
+    Some((_, '(')) => ('(', ')'),
+    _ => ('{', '}')
+  };
+
+  let mut depth = 0;
+  for (j, ch) in chars.by_ref() {
+    word.push(ch);
+    if ch == open {
+      depth += 1;
+    } else if ch == close {
+      depth -= 1;
+      if depth == 0 {
+        return j + 1;
+      }
+    }
+  }
+
+  len
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn words (input: &str) -> Vec<String>
+  {
+    tokenize(input).into_iter().map(|t| t.text).collect()
+  }
+
+  #[test]
+  fn test_tokenize_simple () {
+    assert_eq!(words("ls -la /tmp"), vec!["ls", "-la", "/tmp"]);
+  }
+
+  #[test]
+  fn test_tokenize_quotes () {
+    assert_eq!(words("cd 'my dir'"), vec!["cd", "my dir"]);
+    assert_eq!(words("cd \"my \\\"dir\\\"\""), vec!["cd", "my \"dir\""]);
+    assert_eq!(words("cd my\\ dir"), vec!["cd", "my dir"]);
+  }
+
+  #[test]
+  fn test_tokenize_operators () {
+    let tokens = tokenize("cd foo && make 2>/dev/null | tee log");
+    let kinds: Vec<TokenKind> = tokens.iter().map(|t| t.kind).collect();
+    assert_eq!(kinds, vec![TokenKind::Word, TokenKind::Word, TokenKind::Operator, TokenKind::Word,
+      TokenKind::Redirect, TokenKind::Word, TokenKind::Operator, TokenKind::Word, TokenKind::Word]);
+    assert_eq!(tokens[4].text, "2>");
+  }
+
+  #[test]
+  fn test_tokenize_spans () {
+    let input = "echo 'a b' > out";
+    let tokens = tokenize(input);
+    assert_eq!(&input[tokens[1].span.clone()], "'a b'");
+    assert_eq!(&input[tokens[2].span.clone()], ">");
+    assert_eq!(&input[tokens[3].span.clone()], "out");
+  }
+
+  #[test]
+  fn test_tokenize_substitution () {
+    assert_eq!(words("echo $(cd /tmp && pwd) done"), vec!["echo", "$(cd /tmp && pwd)", "done"]);
+  }
+
+  #[test]
+  fn test_tokenize_comment () {
+    assert_eq!(words("ls # list files"), vec!["ls"]);
+  }
+
+  #[test]
+  fn test_split_commands () {
+    let commands = split_commands("cd src; make && (cd ..; ls) | wc -l");
+    let names: Vec<(String, bool)> = commands.iter()
+      .map(|c| (c.name().unwrap().text.clone(), c.subshell))
+      .collect();
+
+    assert_eq!(names, vec![
+      ("cd".to_string(), false),
+      ("make".to_string(), false),
+      ("cd".to_string(), true),
+      ("ls".to_string(), true),
+      ("wc".to_string(), true),
+    ]);
+  }
+
+  #[test]
+  fn test_split_commands_background () {
+    let commands = split_commands("cd /tmp & ls");
+    assert!(commands[0].subshell);
+    assert!(!commands[1].subshell);
+  }
+
+  #[test]
+  fn test_split_commands_drops_redirections () {
+    let commands = split_commands("FOO=1 make > build.log 2>&1");
+    let words: Vec<&str> = commands[0].words.iter().map(|w| w.text.as_str()).collect();
+    assert_eq!(words, vec!["FOO=1", "make"]);
+    assert_eq!(commands[0].name().unwrap().text, "make");
+  }
+
+  #[test]
+  fn test_expand_word () {
+    let lookup = |name: &str| match name {
+      "HOME" => Some("/home/mike".to_string()),
+      "PROJECT" => Some("nl-sh".to_string()),
+      _ => None
+    };
+
+    assert_eq!(expand_word("~", &lookup), "/home/mike");
+    assert_eq!(expand_word("~/src", &lookup), "/home/mike/src");
+    assert_eq!(expand_word("'~/src'", &lookup), "~/src");
+    assert_eq!(expand_word("$HOME/code/$PROJECT", &lookup), "/home/mike/code/nl-sh");
+    assert_eq!(expand_word("\"${PROJECT}-old\"", &lookup), "nl-sh-old");
+    assert_eq!(expand_word("'$PROJECT'", &lookup), "$PROJECT");
+    assert_eq!(expand_word("$MISSING/x", &lookup), "/x");
+  }
+}
@@ -1,9 +1,32 @@
+pub mod agent;
 pub mod anthropic;
 pub mod args;
+pub mod builtin;
+pub mod cache;
+pub mod classifier;
+pub mod color;
 pub mod command;
 pub mod command_history;
+pub mod completion;
+pub mod config;
 pub mod context;
+pub mod directory;
+pub mod environment;
+pub mod highlight;
+pub mod lexer;
 pub mod local;
 pub mod model;
 pub mod openai;
-pub mod shell;
\ No newline at end of file
+pub mod pager;
+pub mod prompt;
+pub mod providers;
+pub mod redact;
+pub mod report;
+pub mod retry;
+pub mod risk;
+pub mod runtime;
+pub mod shell;
+pub mod sse;
+pub mod system;
+pub mod typo;
+pub mod usage;
\ No newline at end of file
@@ -2,21 +2,31 @@ use llama_cpp_rs::LLama;
 use llama_cpp_rs::options::{ModelOptions, PredictOptions};
 use regex::Regex;
 
+use crate::runtime::{cancelled, show_partial, Cancelled};
+
 pub fn 
-local_llm (model_path: &str) -> LLama
+local_llm (model_path: &str) -> Result<LLama, Box<dyn std::error::Error>>
 {
   LLama::new(
     model_path.into(),
     &ModelOptions::default(),
-  ).unwrap()
+  ).map_err(|e| format!("failed to load local model {model_path}: {e}").into())
 }
 
 pub fn 
 issue_local_llm_request (local_llm: &LLama, prompt: &str) -> Result<String, Box<dyn std::error::Error>>
 {
-  let options = PredictOptions::default();
+  // Show each token as it's generated, stopping as soon as the user cancels the request
+  let options = PredictOptions {
+    token_callback: Some(Box::new(|token| {
+      show_partial(&token);
+      !cancelled()
+    })),
+    ..Default::default()
+  };
 
   match local_llm.predict(prompt.into(), options) {
+    Ok(_) if cancelled() => Err(Box::new(Cancelled)),
     Ok(text) => {
       Ok(extract_command(&text).unwrap_or("".to_string()).trim_matches('"').to_string())
     },
@@ -28,7 +38,7 @@ issue_local_llm_request (local_llm: &LLama, prompt: &str) -> Result<String, Box<
 /// example, here's this shell's init prompt response from a local Mistral model:
 /// 
 /// """
-/// >>> Given this output from the POSIX command `uname - smr`, provide the best next command to run within a shell to
+/// \>>> Given this output from the POSIX command `uname - smr`, provide the best next command to run within a shell to
 ///     get specific details of the underlying operating system variant and version. Be terse. Return only the command with no additional explanation or context.
 ///     This should not be a script, but a simple command-line command which is directly executable. 
 ///     For example, on Mac OS, an appropriate command might be simply `sw_vers`.
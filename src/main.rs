@@ -1,4 +1,5 @@
 use clap::{Arg, ArgAction, Command as CommandArg};
+use std::sync::{Arc, Mutex};
 
 use nl_sh::*;
 pub use crate::anthropic::*;
@@ -32,24 +33,45 @@ fn main() -> Result<(), Box<dyn std::error::Error>>
       .long("local")
       .value_name("path")
       .help("Use a local GGUF-based model as a backend, located at the provided path"))
+    .arg(Arg::new("endpoint")
+      .long("endpoint")
+      .value_name("url")
+      .help("Use an OpenAI-compatible API at the provided URL as a backend, such as a local llama.cpp or ollama server"))
+    .arg(Arg::new("endpoint-model")
+      .long("endpoint-model")
+      .value_name("name")
+      .help("The model to request from --endpoint backends; same as the model.endpoint.model setting (default: \"default\")"))
     .arg(Arg::new("stateless")
       .long("stateless")
       .action(ArgAction::SetTrue)
       .default_value("false")
       .help("Disable update of external shell history (default: false)"))
+    .arg(Arg::new("offline")
+      .long("offline")
+      .action(ArgAction::SetTrue)
+      .default_value("false")
+      .help("Guarantee no data leaves this machine; only --local models or a localhost --endpoint are permitted (default: false)"))
     .get_matches();
 
-  let args = Args::new(&matches);
-  let model: Box<dyn Model> = match args.model_type {
-    ModelType::GPT4 => Box::new(GPT { version: gpt4_version(), client: open_ai_api_client() }),
-    ModelType::GPT35 => Box::new(GPT { version: gpt35_version(), client: open_ai_api_client() }),
-    ModelType::Claude => Box::new(Claude { version: claude_3_sonnet(), client: anthropic_client() }),
-    ModelType::Local(ref path) => Box::new(LocalLLM { local: local_llm(path) }),
-  };
+  let mut config = Config::load();
+
+  // Endpoints are always given their model by `model.endpoint.model`, whether chosen here, by `:model` or as a fallback
+  if let Some(model) = matches.get_one::<String>("endpoint-model") {
+    config.set("model.endpoint.model", model);
+  }
+
+  let args = Args::new(&matches, &config);
+  if args.offline {
+    config.set("offline", "true");
+  }
+
+  // Shared by the remote models, which record the tokens each request consumes
+  let usage = Arc::new(Mutex::new(UsageLedger::from_config(&config)));
+  let model = build_model_chain(&args.model_type, &config, &usage)?;
 
   let executor = CommandExecutor {};
-  let mut context = Context::init(&args, &executor, model.as_ref())?;
+  let mut context = Context::init(&args, config, &executor, model.as_ref())?;
 
-  shell_loop(&mut context, model, &executor)?;
+  shell_loop(&mut context, model, &executor, &usage)?;
   Ok(())
 }
\ No newline at end of file
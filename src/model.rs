@@ -1,129 +1,398 @@
 use clust::messages::ClaudeModel;
 use clust::Client as AnthropicClient;
 use llama_cpp_rs::LLama;
-use openai_api_rs::v1::api::Client as OpenAIClient;
-use tokio::runtime::Runtime;
+use std::cell::Cell;
+use std::future::Future;
+use std::path::Path;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
 
+use crate::args::ModelType;
+use crate::providers::truncate;
+
+pub use crate::agent::*;
 pub use crate::anthropic::*;
 pub use crate::command::*;
 pub use crate::context::*;
 pub use crate::local::*;
 pub use crate::openai::*;
+pub use crate::redact::*;
+pub use crate::retry::*;
+pub use crate::runtime::*;
+pub use crate::usage::*;
+
+/// How much of a command's output is sent back to the model, when revising it or planning the next step of a goal
+const FEEDBACK_OUTPUT_CHARS: usize = 2000;
+
+/// A pending model response, which resolves to the model's suggested command
+pub type ModelResponse<'a> = Pin<Box<dyn Future<Output = Result<String, Box<dyn std::error::Error>>> + 'a>>;
 
-/// A `Model` is a shell-facing abstraction hiding either local or remote language model details behind a simple API
+/// A `Model` is a shell-facing abstraction hiding either local or remote language model details behind a simple API.
+/// Requests are asynchronous, so that they can be run on the shared runtime and abandoned if the user cancels them;
+/// see `wait_for`.
 pub trait Model {
-  /// Used strictly for initialization of local context with information used to construct a better command query. Builds local state around
-  /// operating system, versions, kernel information and other data to better inform responses for command queries.
-  fn init_prompt (&self, input: &str) -> Result<String, Box<dyn std::error::Error>>;
+  /// Used strictly for initialization of local context with information used to construct a better command query, as a fallback when
+  /// the operating system can't be identified natively. Builds local state around operating system, versions, kernel information and
+  /// other data to better inform responses for command queries.
+  fn init_prompt (&self, input: &str) -> ModelResponse<'_>;
 
   /// Main query interface; uses the command prompt to collect *NIX commands given the user's input prompt
-  fn ask_model (&self, context: &Context, input: &str) -> Result<String, Box<dyn std::error::Error>>;
+  fn ask_model (&self, context: &Context, input: &str) -> ModelResponse<'_>;
 
   /// A previously-suggested command failed. Provide the failure context back to the model and attempt a correction to the command
-  fn attempt_correction (&self, context: &Context, input: &str, command: &str, output: &CommandOutput) -> Result<String, Box<dyn std::error::Error>>;
+  fn attempt_correction (&self, context: &Context, input: &str, command: &str, output: &CommandOutput) -> ModelResponse<'_>;
+
+  /// A previously-suggested command ran, but the user says it isn't what they wanted. Provide the output and the user's
+  /// complaint back to the model and attempt a better command
+  fn revise_command (&self, context: &Context, input: &str, command: &str, output: &CommandOutput, complaint: &str) -> ModelResponse<'_>;
+
+  /// Works towards a goal one command at a time. Given the steps run so far and their output, propose the next command,
+  /// or reply `DONE` with a summary once the goal is reached; see `parse_step_reply`
+  fn next_step (&self, context: &Context, goal: &str, steps: &[Step]) -> ModelResponse<'_>;
+
+  /// The name of the backend, as shown to the user
+  fn name (&self) -> String;
+
+  /// The name of the backend which answered the most recent request; differs from `name` only for a `FallbackModel`
+  fn answered_by (&self) -> String
+  {
+    self.name()
+  }
+
+  /// Whether the most recent request was answered by a fallback backend, rather than the primary one
+  fn used_fallback (&self) -> bool
+  {
+    false
+  }
 }
 
 /// OpenAI model variants
 pub struct GPT {
   pub version: String,
-  pub client: OpenAIClient
+  pub client: OpenAIClient,
+  pub redactor: Redactor,
+  pub usage: Arc<Mutex<UsageLedger>>,
+  pub policy: RequestPolicy
 }
 
 /// Anthropic model variants
 pub struct Claude {
   pub version: ClaudeModel,
-  pub client: AnthropicClient
+  pub client: AnthropicClient,
+  pub redactor: Redactor,
+  pub usage: Arc<Mutex<UsageLedger>>,
+  pub policy: RequestPolicy
 }
 
 /// Local / Open-Source model variants
 pub struct LocalLLM {
+  pub name: String,
   pub local: LLama
 }
 
+/// Several models in order of preference. Each request goes to the first model, and should it fail, to the next, and
+/// so on; so a rate-limited or unreachable API can fall back to, say, a local model.
+pub struct FallbackModel {
+  models: Vec<Box<dyn Model>>,
+  answered: Cell<usize>,
+}
+
 /// Constructs a prompt given current environment context, and issues a request to one of OpenAI's GPT models via their API
 impl Model for GPT {
-  fn init_prompt (&self, input: &str) -> Result<String, Box<dyn std::error::Error>>
+  fn init_prompt (&self, input: &str) -> ModelResponse<'_>
   {
-    self.request(&build_init_prompt(input))
+    Box::pin(self.request(build_init_prompt(input)))
   }
 
-  fn ask_model (&self, context: &Context, input: &str) -> Result<String, Box<dyn std::error::Error>>
+  fn ask_model (&self, context: &Context, input: &str) -> ModelResponse<'_>
   {
-    self.request(&build_command_prompt(context, input))
+    Box::pin(self.request(build_command_prompt(context, input)))
   }
 
-  fn attempt_correction (&self, context: &Context, input: &str, command: &str, output: &CommandOutput) -> Result<String, Box<dyn std::error::Error>>
+  fn attempt_correction (&self, context: &Context, input: &str, command: &str, output: &CommandOutput) -> ModelResponse<'_>
   {
-    self.request(&build_correction_prompt(context, input, command, output))
+    Box::pin(self.request(build_correction_prompt(context, input, command, output)))
+  }
+
+  fn revise_command (&self, context: &Context, input: &str, command: &str, output: &CommandOutput, complaint: &str) -> ModelResponse<'_>
+  {
+    Box::pin(self.request(build_revision_prompt(context, input, command, output, complaint)))
+  }
+
+  fn next_step (&self, context: &Context, goal: &str, steps: &[Step]) -> ModelResponse<'_>
+  {
+    Box::pin(self.request(build_step_prompt(context, goal, steps)))
+  }
+
+  fn name (&self) -> String
+  {
+    self.version.clone()
   }
 }
 
 impl GPT {
-  fn request (&self, prompt: &str) -> Result<String, Box<dyn std::error::Error>>
+  async fn request (&self, prompt: String) -> Result<String, Box<dyn std::error::Error>>
   {
-    issue_open_ai_request(&self.client, self.version.clone(), prompt)
+    self.usage.lock().unwrap().check_budget(&self.version)?;
+
+    // Secrets are masked before the prompt leaves this machine, and restored in the suggested command
+    let redacted = self.redactor.redact(&prompt);
+    let (response, tokens) = self.policy.run(|| {
+      issue_open_ai_request(&self.client, self.version.clone(), &redacted.text, show_partial)
+    }).await?;
+    self.usage.lock().unwrap().record(&self.version, tokens);
+    Ok(redacted.restore(&response))
   }
 }
 
-/// Constructs a prompt given current environment context, and issues a request to a local Llama model
+/// Constructs a prompt given current environment context, and issues a request to a local Llama model. Inference runs
+/// on the calling thread, stopping early if the request is cancelled.
 impl Model for LocalLLM {
-  fn init_prompt (&self, input: &str) -> Result<String, Box<dyn std::error::Error>> 
+  fn init_prompt (&self, input: &str) -> ModelResponse<'_>
+  {
+    let prompt = build_init_prompt(input);
+    Box::pin(async move { issue_local_llm_request(&self.local, &prompt) })
+  }
+
+  fn ask_model (&self, context: &Context, input: &str) -> ModelResponse<'_>
+  {
+    let prompt = build_command_prompt(context, input);
+    Box::pin(async move { issue_local_llm_request(&self.local, &prompt) })
+  }
+
+  fn attempt_correction (&self, context: &Context, input: &str, command: &str, output: &CommandOutput) -> ModelResponse<'_>
+  {
+    let prompt = build_correction_prompt(context, input, command, output);
+    Box::pin(async move { issue_local_llm_request(&self.local, &prompt) })
+  }
+
+  fn revise_command (&self, context: &Context, input: &str, command: &str, output: &CommandOutput, complaint: &str) -> ModelResponse<'_>
   {
-    issue_local_llm_request(&self.local, &build_init_prompt(input))
+    let prompt = build_revision_prompt(context, input, command, output, complaint);
+    Box::pin(async move { issue_local_llm_request(&self.local, &prompt) })
   }
 
-  fn ask_model (&self, context: &Context, input: &str) -> Result<String, Box<dyn std::error::Error>> 
+  fn next_step (&self, context: &Context, goal: &str, steps: &[Step]) -> ModelResponse<'_>
   {
-    issue_local_llm_request(&self.local, &build_command_prompt(context, input))
+    let prompt = build_step_prompt(context, goal, steps);
+    Box::pin(async move { issue_local_llm_request(&self.local, &prompt) })
   }
 
-  fn attempt_correction (&self, context: &Context, input: &str, command: &str, output: &CommandOutput) -> Result<String, Box<dyn std::error::Error>>
+  fn name (&self) -> String
   {
-    issue_local_llm_request(&self.local, &build_correction_prompt(context, input, command, output))
+    self.name.clone()
   }
 }
 
 /// Constructs a prompt given current environment context, and issues a request to one of Anthropic's Claude models via their API
 impl Model for Claude {
-  fn init_prompt (&self, input: &str) -> Result<String, Box<dyn std::error::Error>>
+  fn init_prompt (&self, input: &str) -> ModelResponse<'_>
   {
-    self.request(&build_init_prompt(input))
+    Box::pin(self.request(build_init_prompt(input)))
   }
 
-  fn ask_model (&self, context: &Context, input: &str) -> Result<String, Box<dyn std::error::Error>>
+  fn ask_model (&self, context: &Context, input: &str) -> ModelResponse<'_>
   {
-    self.request(&build_command_prompt(context, input))
+    Box::pin(self.request(build_command_prompt(context, input)))
   }
 
-  fn attempt_correction (&self, context: &Context, input: &str, command: &str, output: &CommandOutput) -> Result<String, Box<dyn std::error::Error>>
+  fn attempt_correction (&self, context: &Context, input: &str, command: &str, output: &CommandOutput) -> ModelResponse<'_>
   {
-    self.request(&build_correction_prompt(context, input, command, output))
+    Box::pin(self.request(build_correction_prompt(context, input, command, output)))
+  }
+
+  fn revise_command (&self, context: &Context, input: &str, command: &str, output: &CommandOutput, complaint: &str) -> ModelResponse<'_>
+  {
+    Box::pin(self.request(build_revision_prompt(context, input, command, output, complaint)))
+  }
+
+  fn next_step (&self, context: &Context, goal: &str, steps: &[Step]) -> ModelResponse<'_>
+  {
+    Box::pin(self.request(build_step_prompt(context, goal, steps)))
+  }
+
+  fn name (&self) -> String
+  {
+    self.version.to_string()
   }
 }
 
 impl Claude {
-  fn request (&self, prompt: &str) -> Result<String, Box<dyn std::error::Error>> 
+  async fn request (&self, prompt: String) -> Result<String, Box<dyn std::error::Error>>
+  {
+    let name = self.version.to_string();
+    self.usage.lock().unwrap().check_budget(&name)?;
+
+    // Secrets are masked before the prompt leaves this machine, and restored in the suggested command
+    let redacted = self.redactor.redact(&prompt);
+    let (response, tokens) = self.policy.run(|| {
+      issue_anthropic_request(&self.client, self.version.clone(), &redacted.text, show_partial)
+    }).await?;
+    self.usage.lock().unwrap().record(&name, tokens);
+    Ok(redacted.restore(&response))
+  }
+}
+
+impl Model for FallbackModel {
+  fn init_prompt (&self, input: &str) -> ModelResponse<'_>
+  {
+    let requests = self.models.iter().map(|model| model.init_prompt(input)).collect();
+    Box::pin(self.first_answer(requests))
+  }
+
+  fn ask_model (&self, context: &Context, input: &str) -> ModelResponse<'_>
+  {
+    let requests = self.models.iter().map(|model| model.ask_model(context, input)).collect();
+    Box::pin(self.first_answer(requests))
+  }
+
+  fn attempt_correction (&self, context: &Context, input: &str, command: &str, output: &CommandOutput) -> ModelResponse<'_>
+  {
+    let requests = self.models.iter().map(|model| model.attempt_correction(context, input, command, output)).collect();
+    Box::pin(self.first_answer(requests))
+  }
+
+  fn revise_command (&self, context: &Context, input: &str, command: &str, output: &CommandOutput, complaint: &str) -> ModelResponse<'_>
+  {
+    let requests = self.models.iter().map(|model| model.revise_command(context, input, command, output, complaint)).collect();
+    Box::pin(self.first_answer(requests))
+  }
+
+  fn next_step (&self, context: &Context, goal: &str, steps: &[Step]) -> ModelResponse<'_>
+  {
+    let requests = self.models.iter().map(|model| model.next_step(context, goal, steps)).collect();
+    Box::pin(self.first_answer(requests))
+  }
+
+  fn name (&self) -> String
+  {
+    self.models.iter().map(|model| model.name()).collect::<Vec<String>>().join(" -> ")
+  }
+
+  fn answered_by (&self) -> String
+  {
+    self.models[self.answered.get()].name()
+  }
+
+  fn used_fallback (&self) -> bool
+  {
+    self.answered.get() > 0
+  }
+}
+
+impl FallbackModel {
+  pub fn new (models: Vec<Box<dyn Model>>) -> Self
+  {
+    assert!(!models.is_empty(), "a fallback chain needs at least one model");
+    FallbackModel { models, answered: Cell::new(0) }
+  }
+
+  /// Awaits each model's request in turn, until one succeeds. Requests don't begin until they're awaited, so later
+  /// models are only consulted if earlier ones fail.
+  async fn first_answer (&self, requests: Vec<ModelResponse<'_>>) -> Result<String, Box<dyn std::error::Error>>
   {
-    Runtime::new()?
-      .block_on(
-        issue_anthropic_request(&self.client, self.version.clone(), prompt)
-      )
+    let mut failures = Vec::new();
+    for (i, request) in requests.into_iter().enumerate() {
+      match request.await {
+        Ok(response) => {
+          self.answered.set(i);
+          return Ok(response);
+        },
+        Err(e) if e.is::<Cancelled>() => return Err(e),
+        Err(e) => {
+          failures.push(format!("{}: {}", self.models[i].name(), e));
+          clear_partial();
+        }
+      }
+    }
+
+    Err(format!("every backend failed ({})", failures.join("; ")).into())
   }
 }
 
+/// Constructs the backend for a model type
+pub fn
+build_model (model_type: &ModelType, config: &Config, usage: &Arc<Mutex<UsageLedger>>) -> Result<Box<dyn Model>, Box<dyn std::error::Error>>
+{
+  let policy = RequestPolicy::from_config(config, model_type.key());
+  let model: Box<dyn Model> = match model_type {
+    ModelType::GPT4 => Box::new(GPT { version: gpt4_version(), client: open_ai_api_client()?, redactor: Redactor::from_config(config), usage: usage.clone(), policy }),
+    ModelType::GPT35 => Box::new(GPT { version: gpt35_version(), client: open_ai_api_client()?, redactor: Redactor::from_config(config), usage: usage.clone(), policy }),
+    ModelType::Claude(version) => Box::new(Claude { version: version.clone(), client: anthropic_client()?, redactor: Redactor::from_config(config), usage: usage.clone(), policy }),
+    ModelType::Local(path) => {
+      let name = Path::new(path).file_stem().map(|stem| stem.to_string_lossy().into_owned()).unwrap_or(path.clone());
+      Box::new(LocalLLM { name, local: local_llm(path)? })
+    },
+    ModelType::Endpoint(url, name) => Box::new(GPT { version: name.clone(), client: open_ai_endpoint_client(url), redactor: Redactor::from_config(config), usage: usage.clone(), policy }),
+  };
+
+  Ok(model)
+}
+
+/// Constructs the primary backend, followed by any fallbacks listed in the `model.fallback` setting, such as
+/// `model.fallback = gpt4, local:/models/mistral.gguf`. Fallbacks which can't be constructed, perhaps for want of an
+/// API key, are reported and skipped. In offline mode, a chain with any backend which would send data off this machine
+/// is refused.
+pub fn
+build_model_chain (primary: &ModelType, config: &Config, usage: &Arc<Mutex<UsageLedger>>) -> Result<Box<dyn Model>, Box<dyn std::error::Error>>
+{
+  check_offline(primary, config)?;
+  let fallbacks = fallback_model_types(config)?;
+  let model = build_model(primary, config, usage)?;
+  if fallbacks.is_empty() {
+    return Ok(model);
+  }
+
+  let mut models = vec![model];
+  for model_type in fallbacks.iter().filter(|model_type| *model_type != primary) {
+    match build_model(model_type, config, usage) {
+      Ok(model) => models.push(model),
+      Err(e) => eprintln!("skipping fallback backend {}: {e}", model_type.key())
+    }
+  }
+
+  Ok(Box::new(FallbackModel::new(models)))
+}
+
+/// In offline mode, refuses a primary backend or any fallback listed in `model.fallback` which isn't local
+pub fn
+check_offline (primary: &ModelType, config: &Config) -> Result<(), Box<dyn std::error::Error>>
+{
+  if !config.get_bool("offline", false) {
+    return Ok(());
+  }
+
+  if !primary.is_local() {
+    return Err("offline mode only permits a local model, or an endpoint on localhost".into());
+  }
+
+  if fallback_model_types(config)?.iter().any(|model_type| !model_type.is_local()) {
+    return Err("offline mode only permits local backends in model.fallback".into());
+  }
+
+  Ok(())
+}
+
+/// The backends listed in the `model.fallback` setting
+pub fn
+fallback_model_types (config: &Config) -> Result<Vec<ModelType>, Box<dyn std::error::Error>>
+{
+  config.get_list("model.fallback").unwrap_or_default().iter()
+    .map(|spec| ModelType::parse(spec, config).ok_or_else(|| format!("unknown backend \"{spec}\" in model.fallback").into()))
+    .collect()
+}
+
 fn 
 build_init_prompt (arg: &str) -> String 
 {
   format!(
-    "You are being asked to provide a POSIX-compatible command line sequence to gather information about the underlying operating system variant and version.
+    "You are being asked to identify the operating system variant and version of a POSIX system.
     The underlying details according to the command \"uname -smr\" include:
       \"{arg}\"
-    Respond only the specific command-line details to satisfy the request, with no additional context or explanation. Be terse and exact.
-    Since commands differ on various *NIX systems, ensure the command is valid in the environment detailed above.
-    For example: 
-      On a Darwin UNIX system, an appropriate command might be \"sw_vers\". 
-      On a GNU/Linux system, the right command might be \"hostnamectl\" or \"cat /etc/os-release\".
-    Provide the command."
+    Respond only with the name and version of the operating system, on a single line, with no additional context or explanation. Be terse and exact.
+    For example:
+      \"macOS 14.3\", \"Ubuntu 22.04\" or \"FreeBSD 14.0\".
+    If the version can't be determined from these details, give the name alone."
   )
 }
 
@@ -136,7 +405,7 @@ build_command_prompt (context: &Context, arg: &str) -> String
     The underlying kernel and system details according to \"uname -smr\" includes \"{}\"
     Further operating systems details include \"{}\"
     The user's underlying shell is \"{}\"
-    The user's current working directory according to \"pwd\" is \"{}\"
+    The user's current working directory according to \"pwd\" is \"{}\"{}
     Respond only with the specific command-line details to satisfy the request, with no additional context or explanation. Be terse and exact.
     Since commands differ on various *NIX systems, ensure the command is valid in the environment detailed above.
     Here are a few examples, on a Darwin-based UNIX system:
@@ -151,10 +420,19 @@ build_command_prompt (context: &Context, arg: &str) -> String
     If the prompt is a command sequence for a different *NIX system, return the right combination of commands and flags to satisfy the request on the current system.
     If the user's intention requires superuser priviledges, ensure to prefix the command with 'sudo' or an appropriate equivalent given the operating system.
     Here is the user's prompt: 
-      \"{arg}\"", context.uname, context.os, context.shell, context.pwd
+      \"{arg}\"", context.uname, context.os, context.shell, context.pwd, format_details(&context.details)
   )
 }
 
+/// Formats any supplementary environment details as additional lines of the command prompt
+fn
+format_details (details: &[String]) -> String
+{
+  details.iter()
+    .map(|detail| format!("\n    {detail}"))
+    .collect()
+}
+
 fn 
 build_correction_prompt (context: &Context, arg: &str, command: &str, output: &CommandOutput) -> String
 {
@@ -170,3 +448,142 @@ build_correction_prompt (context: &Context, arg: &str, command: &str, output: &C
       output.status_code, output.stderr
   )
 }
+
+fn
+build_revision_prompt (context: &Context, arg: &str, command: &str, output: &CommandOutput, complaint: &str) -> String
+{
+  format!(
+    "In an earlier conversation, the following prompt was given: 
+    {}
+    \nThis resulted in the following proposed command: {}
+    Executing that proposal on this system finished with status code \"{}\", this stdout output: \"{}\" and this stderr output: \"{}\"
+    However, the user says that isn't what they wanted: \"{}\"
+    Given that, suggest an updated command given the constraints of this system's stated environment and the intent of the user. 
+    Follow all earlier instructions; specifically, emit only the command with no additional context or explanation", 
+      build_command_prompt(context, arg),
+      command,
+      output.status_code, truncate(&output.stdout, FEEDBACK_OUTPUT_CHARS), truncate(&output.stderr, FEEDBACK_OUTPUT_CHARS),
+      complaint
+  )
+}
+
+fn
+build_step_prompt (context: &Context, goal: &str, steps: &[Step]) -> String
+{
+  let history = if steps.is_empty() {
+    "No commands have been run yet.".to_string()
+  } else {
+    steps.iter().enumerate()
+      .map(|(i, step)| format!("\n    Step {}: \"{}\" finished with status code \"{}\", this stdout output: \"{}\" and this stderr output: \"{}\"",
+        i + 1, step.command, step.output.status_code, truncate(&step.output.stdout, FEEDBACK_OUTPUT_CHARS),
+        truncate(&step.output.stderr, FEEDBACK_OUTPUT_CHARS)))
+      .collect()
+  };
+
+  format!(
+    "You are working towards a user's goal on their system, one POSIX-compatible command at a time.
+    The underlying kernel and system details according to \"uname -smr\" includes \"{}\"
+    Further operating systems details include \"{}\"
+    The user's underlying shell is \"{}\"
+    The user's current working directory according to \"pwd\" is \"{}\"{}
+    Here is the user's goal: 
+      \"{goal}\"
+    These are the commands run towards the goal so far, and their results: {history}
+    Respond with only the single next command to run, with no additional context or explanation; later commands can depend on its output.
+    If a command failed, try another approach rather than repeating it.
+    Once the goal has been reached, or it can't be reached, respond only with \"{DONE_MARKER}: \" followed by a one-line summary of the outcome for the user.", 
+      context.uname, context.os, context.shell, context.pwd, format_details(&context.details)
+  )
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  /// A backend which answers every request the same way
+  struct Fixed {
+    name: &'static str,
+    response: Result<&'static str, &'static str>,
+  }
+
+  impl Model for Fixed {
+    fn init_prompt (&self, _input: &str) -> ModelResponse<'_>
+    {
+      Box::pin(async move { self.response.map(|r| r.to_string()).map_err(|e| e.into()) })
+    }
+
+    fn ask_model (&self, _context: &Context, input: &str) -> ModelResponse<'_>
+    {
+      self.init_prompt(input)
+    }
+
+    fn attempt_correction (&self, _context: &Context, input: &str, _command: &str, _output: &CommandOutput) -> ModelResponse<'_>
+    {
+      self.init_prompt(input)
+    }
+
+    fn revise_command (&self, _context: &Context, input: &str, _command: &str, _output: &CommandOutput, _complaint: &str) -> ModelResponse<'_>
+    {
+      self.init_prompt(input)
+    }
+
+    fn next_step (&self, _context: &Context, goal: &str, _steps: &[Step]) -> ModelResponse<'_>
+    {
+      self.init_prompt(goal)
+    }
+
+    fn name (&self) -> String
+    {
+      self.name.to_string()
+    }
+  }
+
+  #[test]
+  fn test_fallback () {
+    let model = FallbackModel::new(vec![
+      Box::new(Fixed { name: "claude", response: Err("overloaded_error") }),
+      Box::new(Fixed { name: "gpt-4", response: Ok("ls -la") }),
+      Box::new(Fixed { name: "mistral", response: Ok("ls") }),
+    ]);
+
+    assert_eq!(runtime().block_on(model.init_prompt("list files")).unwrap(), "ls -la");
+    assert_eq!(model.name(), "claude -> gpt-4 -> mistral");
+    assert_eq!(model.answered_by(), "gpt-4");
+    assert!(model.used_fallback());
+
+    let model = FallbackModel::new(vec![
+      Box::new(Fixed { name: "claude", response: Ok("ls -la") }),
+      Box::new(Fixed { name: "gpt-4", response: Ok("ls") }),
+    ]);
+    assert_eq!(runtime().block_on(model.init_prompt("list files")).unwrap(), "ls -la");
+    assert!(!model.used_fallback());
+  }
+
+  #[test]
+  fn test_fallback_exhausted () {
+    let model = FallbackModel::new(vec![
+      Box::new(Fixed { name: "claude", response: Err("overloaded_error") }),
+      Box::new(Fixed { name: "gpt-4", response: Err("HTTP status 429") }),
+    ]);
+
+    let error = runtime().block_on(model.init_prompt("list files")).unwrap_err();
+    assert_eq!(error.to_string(), "every backend failed (claude: overloaded_error; gpt-4: HTTP status 429)");
+  }
+
+  #[test]
+  fn test_check_offline () {
+    let local = ModelType::Local("/models/mistral.gguf".to_string());
+    assert!(check_offline(&ModelType::GPT4, &Config::parse("model.fallback = gpt4")).is_ok());
+    assert!(check_offline(&local, &Config::parse("offline = true\nmodel.fallback = local:/models/llama.gguf")).is_ok());
+    assert!(check_offline(&ModelType::GPT4, &Config::parse("offline = true")).is_err());
+    assert_eq!(check_offline(&local, &Config::parse("offline = true\nmodel.fallback = gpt4")).unwrap_err().to_string(),
+      "offline mode only permits local backends in model.fallback");
+  }
+
+  #[test]
+  fn test_fallback_model_types () {
+    let config = Config::parse("model.fallback = gpt4, local:/models/mistral.gguf");
+    assert_eq!(fallback_model_types(&config).unwrap(), vec![ModelType::GPT4, ModelType::Local("/models/mistral.gguf".to_string())]);
+    assert!(fallback_model_types(&Config::parse("model.fallback = gpt5")).is_err());
+  }
+}
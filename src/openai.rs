@@ -1,8 +1,21 @@
-use openai_api_rs::v1::api::Client as OpenAIClient;
-use openai_api_rs::v1::chat_completion::{self, ChatCompletionRequest};
 use openai_api_rs::v1::common::{GPT3_5_TURBO, GPT4};
+use serde_json::{json, Value};
 use std::env;
 
+use crate::retry::HttpError;
+use crate::sse::EventStream;
+use crate::usage::TokenUsage;
+
+/// The API requests are made to, unless `OPENAI_API_BASE` gives another
+const OPENAI_API_BASE: &str = "https://api.openai.com/v1";
+
+/// Where an OpenAI-compatible API is served, and the key to authenticate with, if any. Requests are made directly
+/// rather than through the client library, which doesn't support streaming.
+pub struct OpenAIClient {
+  pub api_endpoint: String,
+  pub api_key: String,
+}
+
 pub fn
 gpt4_version () -> String
 {
@@ -16,29 +29,76 @@ gpt35_version () -> String
 }
 
 pub fn
-open_ai_api_client () -> OpenAIClient
+open_ai_api_client () -> Result<OpenAIClient, Box<dyn std::error::Error>>
 {
   match env::var("OPENAI_API_KEY") {
-    Ok(key) => OpenAIClient::new(key.to_string()),
-    Err(e) => panic!("OPENAI_API_KEY must be set as an environment variable in order to issue requests to OpenAI APIs: {e}")
+    Ok(key) => {
+      let api_endpoint = env::var("OPENAI_API_BASE").unwrap_or(OPENAI_API_BASE.to_string());
+      Ok(OpenAIClient { api_endpoint, api_key: key })
+    },
+    Err(e) => Err(format!("OPENAI_API_KEY must be set as an environment variable in order to issue requests to OpenAI APIs: {e}").into())
   }
 }
 
+/// A client for an OpenAI-compatible API served elsewhere, such as a local llama.cpp or ollama server. These
+/// rarely require an API key, so OPENAI_API_KEY is used only if it's set.
 pub fn
-issue_open_ai_request (client: &OpenAIClient, model: String, prompt: &str) -> Result<String, Box<dyn std::error::Error>>
+open_ai_endpoint_client (endpoint: &str) -> OpenAIClient
 {
-  let req = ChatCompletionRequest::new(
-    model,
-    vec![chat_completion::ChatCompletionMessage {
-        role: chat_completion::MessageRole::user,
-        content: chat_completion::Content::Text(String::from(prompt)),
-        name: None,
-    }],
-  );
-
-  let response = client.chat_completion(req)?;
-  match response.choices[0].message.content.clone() {
-    Some(message) => Ok(message.trim_matches('"').to_string()),
-    _ => Ok("".to_string())
+  let api_key = env::var("OPENAI_API_KEY").unwrap_or_default();
+  OpenAIClient { api_endpoint: endpoint.to_string(), api_key }
+}
+
+/// Issues a chat completion request, streaming the response; each fragment of text is passed to `on_text` as it
+/// arrives. Returns the whole response along with the tokens it consumed.
+pub async fn
+issue_open_ai_request (client: &OpenAIClient, model: String, prompt: &str, on_text: impl Fn(&str)) -> Result<(String, TokenUsage), Box<dyn std::error::Error>>
+{
+  let body = json!({
+    "model": model,
+    "messages": [{ "role": "user", "content": prompt }],
+    "stream": true,
+    "stream_options": { "include_usage": true },
+  });
+
+  let mut request = reqwest::Client::new()
+    .post(format!("{}/chat/completions", client.api_endpoint.trim_end_matches('/')))
+    .json(&body);
+  if !client.api_key.is_empty() {
+    request = request.bearer_auth(&client.api_key);
   }
-}
\ No newline at end of file
+
+  let mut response = request.send().await?;
+  if !response.status().is_success() {
+    let status = response.status().as_u16();
+    return Err(Box::new(HttpError { status, body: response.text().await.unwrap_or_default() }));
+  }
+
+  let mut events = EventStream::new();
+  let mut text = String::new();
+  let mut usage = TokenUsage::default();
+
+  while let Some(chunk) = response.chunk().await? {
+    for data in events.push(&chunk) {
+      if data == "[DONE]" {
+        continue;
+      }
+
+      let event: Value = serde_json::from_str(&data)?;
+      if let Some(fragment) = event["choices"][0]["delta"]["content"].as_str() {
+        text.push_str(fragment);
+        on_text(fragment);
+      }
+
+      // Usage is reported in a final event, with no choices
+      if event["usage"].is_object() {
+        usage = TokenUsage {
+          input: event["usage"]["prompt_tokens"].as_u64().unwrap_or(0),
+          output: event["usage"]["completion_tokens"].as_u64().unwrap_or(0),
+        };
+      }
+    }
+  }
+
+  Ok((text.trim().trim_matches('"').to_string(), usage))
+}
@@ -0,0 +1,224 @@
+use crossterm::event::{self, Event, KeyCode, KeyModifiers};
+use crossterm::terminal;
+use std::io::{self, IsTerminal, Write};
+use std::process::{Command, Stdio};
+
+use crate::config::Config;
+use crate::environment::Environment;
+use crate::lexer::split_commands;
+
+/// The pager used when neither `pager.program` nor `$PAGER` is set; `-R` passes colours through
+const DEFAULT_PAGER: &str = "less -R";
+
+/// Names `pager.program` can be given to use the built-in pager
+const BUILTIN_PAGER: &str = "builtin";
+
+/// Writes a command's output to the terminal, through a pager when it's longer than the terminal is tall.
+///
+/// Paging is controlled by the `pager` setting: `auto` (the default) pages output which doesn't fit, `always` pages
+/// all output, and `off` never pages. Individual commands can be overridden with `pager.<command>`, so
+/// `pager.tail = off` leaves the output of `tail` unpaged. Output is piped to `pager.program`, or otherwise `$PAGER`
+/// or `less -R`; `pager.program = builtin` uses a simple pager built into nl-sh.
+pub fn
+show_output (raw: &[u8], command: &str, config: &Config, shell: &str, env: &Environment) -> io::Result<()>
+{
+  let mut stdout = io::stdout();
+  let size = terminal::size().ok().map(|(columns, rows)| (columns as usize, rows as usize));
+
+  let paged = match size {
+    Some((columns, rows)) if stdout.is_terminal() && io::stdin().is_terminal() => {
+      let text = String::from_utf8_lossy(raw);
+      should_page(command, config, display_rows(&text, columns), rows)
+    },
+    _ => false
+  };
+
+  if paged {
+    let program = config.get("pager.program").map(str::to_string)
+      .or_else(|| env.get("PAGER"))
+      .filter(|program| !program.trim().is_empty())
+      .unwrap_or_else(|| DEFAULT_PAGER.to_string());
+
+    if program != BUILTIN_PAGER {
+      match external_pager(&program, raw, shell, env) {
+        Ok(()) => return Ok(()),
+        Err(e) => eprintln!("pager {program} failed: {e}; using the built-in pager")
+      }
+    }
+
+    return builtin_pager(&String::from_utf8_lossy(raw));
+  }
+
+  stdout.write_all(b"\n")?;
+  stdout.write_all(raw)?;
+  stdout.flush()
+}
+
+/// Whether output taking `rows` rows on screen should be paged, on a terminal `height` rows tall
+fn
+should_page (command: &str, config: &Config, rows: usize, height: usize) -> bool
+{
+  // Any command in the command line can opt in or out, as in `git log | head` with `pager.head = off`
+  let overrides: Vec<bool> = split_commands(command).iter()
+    .filter_map(|simple| simple.name())
+    .filter_map(|name| config.get(&format!("pager.{}", name.text)).map(|_| config.get_bool(&format!("pager.{}", name.text), true)))
+    .collect();
+
+  if overrides.contains(&false) {
+    return false;
+  }
+
+  match config.get("pager").unwrap_or("auto") {
+    "off" | "false" | "no" | "never" => false,
+    "always" => rows > 0,
+    // Leave room for the prompt which follows
+    _ => (overrides.contains(&true) && rows > 0) || rows > height.saturating_sub(2)
+  }
+}
+
+/// Runs the pager through the shell, as `$PAGER` may include arguments, writing the output to it
+fn
+external_pager (program: &str, raw: &[u8], shell: &str, env: &Environment) -> io::Result<()>
+{
+  let mut command = Command::new(shell);
+  env.apply(&mut command);
+
+  let mut child = command.arg("-c").arg(program).stdin(Stdio::piped()).spawn()?;
+  if let Some(mut stdin) = child.stdin.take() {
+    // The pager may be quit before reading everything, closing the pipe
+    match stdin.write_all(raw) {
+      Err(e) if e.kind() == io::ErrorKind::BrokenPipe => {},
+      result => result?
+    }
+  }
+
+  child.wait()?;
+  Ok(())
+}
+
+/// The number of rows `text` takes on a terminal `columns` wide, as long lines wrap
+fn
+display_rows (text: &str, columns: usize) -> usize
+{
+  text.lines().map(|line| wrap_rows(line, columns).len()).sum()
+}
+
+/// Splits a line into rows no wider than `columns` characters. Escape sequences, such as colours, take no space and
+/// are kept with the characters they precede.
+fn
+wrap_rows (line: &str, columns: usize) -> Vec<String>
+{
+  let columns = columns.max(1);
+  let mut rows = Vec::new();
+  let mut row = String::new();
+  let mut width = 0;
+  let mut chars = line.chars().peekable();
+
+  while let Some(c) = chars.next() {
+    if c == '\x1b' {
+      // Copy a CSI sequence, such as `\x1b[1;32m`, up to and including its final byte
+      row.push(c);
+      if chars.peek() == Some(&'[') {
+        row.push(chars.next().unwrap_or('['));
+        for c in chars.by_ref() {
+          row.push(c);
+          if ('@'..='~').contains(&c) {
+            break;
+          }
+        }
+      }
+      continue;
+    }
+
+    if width == columns {
+      rows.push(std::mem::take(&mut row));
+      width = 0;
+    }
+
+    row.push(c);
+    width += if c == '\t' { 8 - width % 8 } else { 1 };
+  }
+
+  rows.push(row);
+  rows
+}
+
+/// A minimal pager: Space or PageDown for the next page, Enter, Down or `j` for the next line, Up or `k` for the
+/// previous line, PageUp or `b` for the previous page, `g` and `G` for the start and end, and `q` or Esc to quit
+fn
+builtin_pager (text: &str) -> io::Result<()>
+{
+  let mut stdout = io::stdout();
+  terminal::enable_raw_mode()?;
+  write!(stdout, "\x1b[?1049h")?;
+
+  let result = (|| -> io::Result<()> {
+    let mut top = 0;
+    loop {
+      let (columns, height) = terminal::size().map(|(c, r)| (c as usize, r as usize))?;
+      let rows: Vec<String> = text.lines().flat_map(|line| wrap_rows(line, columns)).collect();
+      let page = height.saturating_sub(1).max(1);
+      let last = rows.len().saturating_sub(page);
+      top = top.min(last);
+
+      write!(stdout, "\x1b[2J\x1b[H")?;
+      for row in rows.iter().skip(top).take(page) {
+        write!(stdout, "{row}\x1b[0m\r\n")?;
+      }
+
+      let end = (top + page).min(rows.len());
+      let status = format!("lines {}-{} of {}{} (q to quit)", top + 1, end, rows.len(), if end == rows.len() { " (end)" } else { "" });
+      write!(stdout, "\x1b[{height};1H\x1b[7m{status}\x1b[0m")?;
+      stdout.flush()?;
+
+      if let Event::Key(key) = event::read()? {
+        let interrupt = key.code == KeyCode::Char('c') && key.modifiers.contains(KeyModifiers::CONTROL);
+        top = match key.code {
+          _ if interrupt => break,
+          KeyCode::Char('q') | KeyCode::Esc => break,
+          KeyCode::Char(' ') | KeyCode::PageDown | KeyCode::Char('f') => top + page,
+          KeyCode::Enter | KeyCode::Down | KeyCode::Char('j') => top + 1,
+          KeyCode::Up | KeyCode::Char('k') => top.saturating_sub(1),
+          KeyCode::PageUp | KeyCode::Char('b') => top.saturating_sub(page),
+          KeyCode::Char('g') => 0,
+          KeyCode::Char('G') => last,
+          _ => top
+        };
+      }
+    }
+    Ok(())
+  })();
+
+  write!(stdout, "\x1b[?1049l")?;
+  stdout.flush()?;
+  terminal::disable_raw_mode()?;
+  result
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_wrap_rows () {
+    assert_eq!(wrap_rows("abcdef", 4), vec!["abcd".to_string(), "ef".to_string()]);
+    assert_eq!(wrap_rows("\x1b[1;32mab\x1b[0mcd", 2), vec!["\x1b[1;32mab\x1b[0m".to_string(), "cd".to_string()]);
+    assert_eq!(wrap_rows("", 10), vec![String::new()]);
+    assert_eq!(display_rows("a\nbbbbb\n\nc", 4), 5);
+  }
+
+  #[test]
+  fn test_should_page () {
+    let config = Config::new();
+    assert!(should_page("git log", &config, 30, 24));
+    assert!(!should_page("git log", &config, 22, 24));
+
+    let config = Config::parse("pager.head = off\npager.ls = on");
+    assert!(!should_page("git log | head -n 50", &config, 50, 24));
+    assert!(should_page("ls", &config, 3, 24));
+    assert!(!should_page("ls", &config, 0, 24));
+
+    assert!(!should_page("git log", &Config::parse("pager = off"), 100, 24));
+    assert!(should_page("git log", &Config::parse("pager = always"), 1, 24));
+  }
+}
@@ -0,0 +1,233 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use crate::directory::abbreviate_home;
+use crate::highlight::RESET;
+
+/// The prompt used unless `prompt` is configured, such as `[nl-sh] /home/mike $`
+pub const DEFAULT_PROMPT: &str = "[\\s] \\P $";
+
+/// Colours and styles which can be named in a prompt template, as in `{green}[\s]{reset}`
+static COLORS: &[(&str, &str)] = &[("black", "\x1b[30m"), ("red", "\x1b[31m"), ("green", "\x1b[32m"),
+  ("yellow", "\x1b[33m"), ("blue", "\x1b[34m"), ("magenta", "\x1b[35m"), ("cyan", "\x1b[36m"), ("white", "\x1b[37m"),
+  ("bold", "\x1b[1m"), ("dim", "\x1b[2m"), ("reset", RESET)];
+
+/// The facts a prompt template can refer to. Those which are costly to find, such as the git branch, are only
+/// gathered when the template uses them.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct PromptInfo {
+  /// Whether the session is in offline mode, which is always marked ahead of the template
+  pub offline: bool,
+  pub pwd: String,
+  pub home: String,
+  pub user: String,
+  pub host: String,
+  pub branch: Option<String>,
+  /// Exit status of the last command run, if any
+  pub status: Option<i32>,
+  pub model: String,
+  /// Cost of this session's model requests, in US dollars
+  pub cost: f64,
+  pub time: String,
+}
+
+impl PromptInfo
+{
+  /// Fills in the facts which `template` needs but aren't already known
+  pub fn gather (mut self, template: &str) -> Self
+  {
+    if template.contains("\\b") {
+      self.branch = git_branch(Path::new(&self.pwd));
+    }
+    if template.contains("\\h") || template.contains("\\H") {
+      self.host = hostname().unwrap_or_default();
+    }
+    if template.contains("\\t") || template.contains("\\A") {
+      self.time = current_time().unwrap_or_default();
+    }
+    self
+  }
+}
+
+/// Renders a prompt template. Tokens are introduced by a backslash, as in bash's `PS1`:
+///
+/// - `\w` the working directory, with the home directory abbreviated to `~`; `\P` the full working directory; `\W`
+///   the last component of the working directory
+/// - `\u` the user; `\h` the host name up to the first `.`; `\H` the full host name
+/// - `\b` the git branch of the working directory, if any
+/// - `\?` the exit status of the last command
+/// - `\m` the model backend; `\c` the session's model cost
+/// - `\t` the time, as `HH:MM:SS`; `\A` as `HH:MM`
+/// - `\s` "nl-sh"; `\$` `#` for root, otherwise `$`; `\n` a newline; `\\` a backslash
+///
+/// Colours are named in braces, such as `{green}`, `{bold}` and `{reset}`, and dropped when `colors` is false. Unknown
+/// tokens are kept as written. In offline mode the prompt always begins with `[offline]`, whatever the template.
+pub fn
+render_prompt (template: &str, info: &PromptInfo, colors: bool) -> String
+{
+  let mut prompt = if info.offline { "[offline] ".to_string() } else { String::new() };
+  let mut colored = false;
+  let mut chars = template.chars().peekable();
+
+  while let Some(c) = chars.next() {
+    match c {
+      '\\' => {
+        let token = match chars.next() {
+          Some(token) => token,
+          None => {
+            prompt.push('\\');
+            break;
+          }
+        };
+
+        match token {
+          'w' => prompt.push_str(&abbreviate_home(&info.pwd, &info.home)),
+          'P' => prompt.push_str(&info.pwd),
+          'W' => prompt.push_str(Path::new(&info.pwd).file_name().and_then(|name| name.to_str()).unwrap_or(&info.pwd)),
+          'u' => prompt.push_str(&info.user),
+          'h' => prompt.push_str(info.host.split('.').next().unwrap_or_default()),
+          'H' => prompt.push_str(&info.host),
+          'b' => prompt.push_str(info.branch.as_deref().unwrap_or_default()),
+          '?' => prompt.push_str(&info.status.unwrap_or(0).to_string()),
+          'm' => prompt.push_str(&info.model),
+          'c' => prompt.push_str(&format!("${:.3}", info.cost)),
+          't' => prompt.push_str(&info.time),
+          'A' => prompt.push_str(info.time.get(..5).unwrap_or(&info.time)),
+          's' => prompt.push_str("nl-sh"),
+          '$' => prompt.push(if info.user == "root" { '#' } else { '$' }),
+          'n' => prompt.push('\n'),
+          '\\' => prompt.push('\\'),
+          other => {
+            prompt.push('\\');
+            prompt.push(other);
+          }
+        }
+      },
+      '{' => {
+        let rest: String = chars.clone().take_while(|&c| c != '}').collect();
+        let closed = chars.clone().nth(rest.chars().count()) == Some('}');
+        match COLORS.iter().find(|(name, _)| closed && *name == rest) {
+          Some((_, code)) => {
+            // Skip the name and closing brace
+            chars.nth(rest.chars().count());
+            if colors {
+              prompt.push_str(code);
+              colored = true;
+            }
+          },
+          None => prompt.push('{')
+        }
+      },
+      c => prompt.push(c)
+    }
+  }
+
+  // Keep colours from running into the input
+  if colored {
+    prompt.push_str(RESET);
+  }
+
+  prompt
+}
+
+/// The branch checked out in the git repository containing `dir`, or an abbreviated commit hash when detached
+fn
+git_branch (dir: &Path) -> Option<String>
+{
+  let git_dir = dir.ancestors().find_map(|ancestor| {
+    let git = ancestor.join(".git");
+    if git.is_dir() {
+      Some(git)
+    } else if git.is_file() {
+      // Worktrees and submodules have a file pointing to the repository's directory
+      let contents = fs::read_to_string(&git).ok()?;
+      let path = PathBuf::from(contents.strip_prefix("gitdir:")?.trim());
+      Some(if path.is_absolute() { path } else { ancestor.join(path) })
+    } else {
+      None
+    }
+  })?;
+
+  let head = fs::read_to_string(git_dir.join("HEAD")).ok()?;
+  match head.trim().strip_prefix("ref: refs/heads/") {
+    Some(branch) => Some(branch.to_string()),
+    None => head.get(..7).map(|hash| hash.to_string())
+  }
+}
+
+fn
+hostname () -> Option<String>
+{
+  fs::read_to_string("/proc/sys/kernel/hostname").ok()
+    .or_else(|| Command::new("hostname").output().ok().map(|output| String::from_utf8_lossy(&output.stdout).into_owned()))
+    .map(|host| host.trim().to_string())
+    .filter(|host| !host.is_empty())
+}
+
+/// The local time, as `HH:MM:SS`
+fn
+current_time () -> Option<String>
+{
+  let output = Command::new("date").arg("+%H:%M:%S").output().ok()?;
+  Some(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn info () -> PromptInfo
+  {
+    PromptInfo {
+      offline: false,
+      pwd: "/home/mike/src/nl-sh".to_string(),
+      home: "/home/mike".to_string(),
+      user: "mike".to_string(),
+      host: "box.example.com".to_string(),
+      branch: Some("main".to_string()),
+      status: Some(2),
+      model: "claude-3-sonnet".to_string(),
+      cost: 0.0125,
+      time: "09:41:05".to_string(),
+    }
+  }
+
+  #[test]
+  fn test_default_prompt () {
+    assert_eq!(render_prompt(DEFAULT_PROMPT, &info(), true), "[nl-sh] /home/mike/src/nl-sh $");
+  }
+
+  #[test]
+  fn test_tokens () {
+    assert_eq!(render_prompt("\\u@\\h:\\w (\\b) [\\?] \\m \\c \\A\\n\\W \\\\ \\x", &info(), false),
+      "mike@box:~/src/nl-sh (main) [2] claude-3-sonnet $0.013 09:41\nnl-sh \\ \\x");
+  }
+
+  #[test]
+  fn test_offline () {
+    let offline = PromptInfo { offline: true, ..info() };
+    assert_eq!(render_prompt(DEFAULT_PROMPT, &offline, false), "[offline] [nl-sh] /home/mike/src/nl-sh $");
+    assert_eq!(render_prompt("\\W \\$", &offline, true), "[offline] nl-sh $");
+  }
+
+  #[test]
+  fn test_colors () {
+    assert_eq!(render_prompt("{green}\\s{reset} {nope}", &info(), true), "\x1b[32mnl-sh\x1b[0m {nope}\x1b[0m");
+    assert_eq!(render_prompt("{green}\\s{reset} {nope}", &info(), false), "nl-sh {nope}");
+    assert_eq!(render_prompt("{red", &info(), true), "{red");
+  }
+
+  #[test]
+  fn test_git_branch () {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let nested = temp_dir.path().join("src");
+    fs::create_dir_all(temp_dir.path().join(".git")).unwrap();
+    fs::create_dir_all(&nested).unwrap();
+    fs::write(temp_dir.path().join(".git").join("HEAD"), "ref: refs/heads/feature/prompt\n").unwrap();
+    assert_eq!(git_branch(&nested), Some("feature/prompt".to_string()));
+
+    fs::write(temp_dir.path().join(".git").join("HEAD"), "3f2a1b9c0d\n").unwrap();
+    assert_eq!(git_branch(&nested), Some("3f2a1b9".to_string()));
+  }
+}
@@ -0,0 +1,305 @@
+use std::cell::RefCell;
+use std::fs;
+use std::path::Path;
+
+use crate::command::CommandExecutorInterface;
+use crate::config::Config;
+use crate::context::Context;
+
+/// Commonly-used command line tools whose availability is worth telling the model about, since several have
+/// more popular alternatives which may not be installed (`rg` versus `grep`, `fd` versus `find` and so on)
+static COMMON_TOOLS: &[&str] = &["rg", "ag", "grep", "fd", "find", "fzf", "jq", "yq", "curl", "wget", "git", "gh",
+  "docker", "podman", "kubectl", "python3", "pip3", "node", "npm", "cargo", "go", "make", "gsed", "gawk", "bat",
+  "tree", "htop", "lsof", "ss", "netstat", "ip", "ifconfig", "rsync", "tmux", "systemctl", "journalctl"];
+
+/// Files which identify the type of project in a directory
+static PROJECT_FILES: &[(&str, &str)] = &[
+  ("Cargo.toml", "Rust (cargo)"),
+  ("package.json", "Node.js (npm)"),
+  ("pyproject.toml", "Python (pyproject)"),
+  ("requirements.txt", "Python (pip)"),
+  ("setup.py", "Python (setuptools)"),
+  ("go.mod", "Go modules"),
+  ("pom.xml", "Java (Maven)"),
+  ("build.gradle", "Gradle"),
+  ("build.gradle.kts", "Gradle"),
+  ("Gemfile", "Ruby (Bundler)"),
+  ("composer.json", "PHP (Composer)"),
+  ("mix.exs", "Elixir (Mix)"),
+  ("CMakeLists.txt", "CMake"),
+  ("Makefile", "Make"),
+  ("Dockerfile", "Docker"),
+  ("docker-compose.yml", "Docker Compose"),
+  ("compose.yaml", "Docker Compose"),
+];
+
+/// Default limit on the size of any one provider's contribution to a prompt, in characters
+const DEFAULT_MAX_CHARS: usize = 800;
+
+/// Default limit on the number of directory entries listed
+const DEFAULT_MAX_ENTRIES: usize = 40;
+
+/// A source of optional detail about the user's environment, added to command prompts so the model can
+/// suggest commands which actually work here; for example, avoiding tools which aren't installed, or files
+/// which don't exist.
+pub trait ContextProvider {
+  /// The provider's name, which is also its `context.<name>` configuration toggle
+  fn name (&self) -> &'static str;
+
+  /// Describes this provider's facet of the environment, or `None` if there's nothing relevant to add
+  fn provide (&self, context: &Context, executor: &dyn CommandExecutorInterface) -> Option<String>;
+}
+
+/// Reports which of a set of common command line tools are installed. Checked once per session.
+pub struct ToolsProvider {
+  tools: Vec<String>,
+  installed: RefCell<Option<Vec<String>>>,
+}
+
+/// Lists the contents of the current working directory
+pub struct ListingProvider {
+  max_entries: usize,
+}
+
+/// Reports whether the current working directory is within a git repository, and its branch and status
+pub struct GitProvider;
+
+/// Identifies the kind of project in the current working directory from well-known build files
+pub struct ProjectProvider;
+
+/// The set of enabled `ContextProvider`s, with a bound on how much each may contribute
+pub struct ContextProviders {
+  providers: Vec<Box<dyn ContextProvider>>,
+  max_chars: usize,
+}
+
+impl ContextProvider for ToolsProvider
+{
+  fn name (&self) -> &'static str
+  {
+    "tools"
+  }
+
+  fn provide (&self, context: &Context, executor: &dyn CommandExecutorInterface) -> Option<String>
+  {
+    let mut installed = self.installed.borrow_mut();
+    let installed = installed.get_or_insert_with(|| {
+      self.tools.iter()
+        .filter(|tool| executor.exists(&context.shell, tool, &context.env))
+        .cloned()
+        .collect()
+    });
+
+    let missing: Vec<&String> = self.tools.iter().filter(|tool| !installed.contains(tool)).collect();
+    let missing = if missing.is_empty() {
+      String::new()
+    } else {
+      format!("; these are NOT installed: {}", missing.iter().map(|t| t.as_str()).collect::<Vec<&str>>().join(", "))
+    };
+
+    Some(format!("Installed command line tools include: {}{missing}", installed.join(", ")))
+  }
+}
+
+impl ContextProvider for ListingProvider
+{
+  fn name (&self) -> &'static str
+  {
+    "listing"
+  }
+
+  fn provide (&self, context: &Context, _executor: &dyn CommandExecutorInterface) -> Option<String>
+  {
+    let mut entries: Vec<String> = fs::read_dir(&context.pwd).ok()?
+      .filter_map(|entry| entry.ok())
+      .map(|entry| {
+        let name = entry.file_name().to_string_lossy().into_owned();
+        if entry.file_type().is_ok_and(|t| t.is_dir()) { format!("{name}/") } else { name }
+      })
+      .collect();
+
+    if entries.is_empty() {
+      return Some("The current working directory is empty".to_string());
+    }
+
+    entries.sort();
+    let total = entries.len();
+    entries.truncate(self.max_entries);
+
+    let more = if total > entries.len() { format!(" (and {} more)", total - entries.len()) } else { String::new() };
+    Some(format!("The current working directory contains: {}{more}", entries.join(", ")))
+  }
+}
+
+impl ContextProvider for GitProvider
+{
+  fn name (&self) -> &'static str
+  {
+    "git"
+  }
+
+  fn provide (&self, context: &Context, executor: &dyn CommandExecutorInterface) -> Option<String>
+  {
+    let output = executor.execute(&context.shell, "git status --porcelain --branch", &context.env).ok()?;
+    if output.success {
+      describe_git_status(&output.stdout)
+    } else {
+      None
+    }
+  }
+}
+
+impl ContextProvider for ProjectProvider
+{
+  fn name (&self) -> &'static str
+  {
+    "project"
+  }
+
+  fn provide (&self, context: &Context, _executor: &dyn CommandExecutorInterface) -> Option<String>
+  {
+    let kinds: Vec<String> = PROJECT_FILES.iter()
+      .filter(|(file, _)| Path::new(&context.pwd).join(file).exists())
+      .map(|(file, kind)| format!("{kind} from {file}"))
+      .collect();
+
+    if kinds.is_empty() {
+      None
+    } else {
+      Some(format!("The current working directory is a project using: {}", kinds.join(", ")))
+    }
+  }
+}
+
+impl ContextProviders
+{
+  /// Builds the providers enabled in the user's configuration; all are enabled by default
+  pub fn from_config (config: &Config) -> Self
+  {
+    let tools = config.get_list("context.tools.list")
+      .unwrap_or_else(|| COMMON_TOOLS.iter().map(|tool| tool.to_string()).collect());
+
+    let candidates: Vec<Box<dyn ContextProvider>> = vec![
+      Box::new(ToolsProvider { tools, installed: RefCell::new(None) }),
+      Box::new(ProjectProvider),
+      Box::new(GitProvider),
+      Box::new(ListingProvider { max_entries: config.get_usize("context.listing.max_entries", DEFAULT_MAX_ENTRIES) }),
+    ];
+
+    ContextProviders {
+      providers: candidates.into_iter()
+        .filter(|provider| config.get_bool(&format!("context.{}", provider.name()), true))
+        .collect(),
+      max_chars: config.get_usize("context.max_chars", DEFAULT_MAX_CHARS),
+    }
+  }
+
+  /// Collects details from each enabled provider, each truncated to the configured size bound
+  pub fn gather (&self, context: &Context, executor: &dyn CommandExecutorInterface) -> Vec<String>
+  {
+    self.providers.iter()
+      .filter_map(|provider| provider.provide(context, executor))
+      .map(|detail| truncate(&detail, self.max_chars))
+      .collect()
+  }
+}
+
+/// Summarizes the output of `git status --porcelain --branch`, which begins with a line such as
+///   ## main...origin/main [ahead 1]
+fn
+describe_git_status (status: &str) -> Option<String>
+{
+  let mut lines = status.lines();
+  let branch = lines.next()?.strip_prefix("## ")?;
+  let branch = branch.split("...").next().unwrap_or(branch).trim();
+  let changes = lines.filter(|line| !line.trim().is_empty()).count();
+
+  Some(format!("The current working directory is within a git repository, on branch \"{branch}\", with {changes} changed or untracked files"))
+}
+
+/// Truncates `text` to at most `max_chars` characters, marking where it was cut
+pub fn
+truncate (text: &str, max_chars: usize) -> String
+{
+  match text.char_indices().nth(max_chars) {
+    Some((index, _)) => format!("{}...", &text[..index]),
+    None => text.to_string()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::shell::*;
+  use mockall::predicate::*;
+
+  #[test]
+  fn test_tools_provider () {
+    let mut mock_executor = MockCommandExecutorInterface::new();
+    mock_executor.expect_exists()
+      .with(eq("/bin/zsh"), eq("grep"), always())
+      .times(1)
+      .returning(|_, _, _| true);
+    mock_executor.expect_exists()
+      .with(eq("/bin/zsh"), eq("rg"), always())
+      .times(1)
+      .returning(|_, _, _| false);
+
+    let provider = ToolsProvider { tools: vec!["rg".to_string(), "grep".to_string()], installed: RefCell::new(None) };
+    let context = Context::for_test("/");
+    let expected = "Installed command line tools include: grep; these are NOT installed: rg";
+
+    assert_eq!(provider.provide(&context, &mock_executor).unwrap(), expected);
+
+    // Results are cached for the session
+    assert_eq!(provider.provide(&context, &mock_executor).unwrap(), expected);
+  }
+
+  #[test]
+  fn test_listing_provider () {
+    let temp_dir = tempfile::tempdir().unwrap();
+    fs::create_dir(temp_dir.path().join("src")).unwrap();
+    fs::write(temp_dir.path().join("b.txt"), "").unwrap();
+    fs::write(temp_dir.path().join("a.txt"), "").unwrap();
+
+    let context = Context::for_test(temp_dir.path().to_str().unwrap());
+    let mock_executor = MockCommandExecutorInterface::new();
+
+    let provider = ListingProvider { max_entries: 2 };
+    assert_eq!(provider.provide(&context, &mock_executor).unwrap(),
+      "The current working directory contains: a.txt, b.txt (and 1 more)");
+  }
+
+  #[test]
+  fn test_project_provider () {
+    let temp_dir = tempfile::tempdir().unwrap();
+    fs::write(temp_dir.path().join("Cargo.toml"), "").unwrap();
+
+    let context = Context::for_test(temp_dir.path().to_str().unwrap());
+    let mock_executor = MockCommandExecutorInterface::new();
+
+    assert_eq!(ProjectProvider.provide(&context, &mock_executor).unwrap(),
+      "The current working directory is a project using: Rust (cargo) from Cargo.toml");
+  }
+
+  #[test]
+  fn test_describe_git_status () {
+    assert_eq!(describe_git_status("## main...origin/main [ahead 1]\n M src/shell.rs\n?? notes.txt\n").unwrap(),
+      "The current working directory is within a git repository, on branch \"main\", with 2 changed or untracked files");
+    assert_eq!(describe_git_status(""), None);
+  }
+
+  #[test]
+  fn test_from_config () {
+    let config = Config::parse("context.git = false\ncontext.listing = off");
+    let providers = ContextProviders::from_config(&config);
+    let names: Vec<&str> = providers.providers.iter().map(|p| p.name()).collect();
+    assert_eq!(names, vec!["tools", "project"]);
+  }
+
+  #[test]
+  fn test_truncate () {
+    assert_eq!(truncate("abcdef", 3), "abc...");
+    assert_eq!(truncate("abc", 3), "abc");
+  }
+}
@@ -0,0 +1,93 @@
+use std::io::{self, Write};
+use std::process::Command;
+use std::time::Duration;
+
+use crate::config::Config;
+use crate::highlight::{paint, BOLD_RED, DIM, RESET};
+
+/// Summarises how an executed command ended, such as `[exit 0 · 1.24s]`; shown after its output. A failing status is
+/// highlighted when `colors` is set.
+pub fn
+footer (status_code: i32, elapsed: Duration, colors: bool) -> String
+{
+  let status = format!("exit {status_code}");
+  if !colors {
+    return format!("[{status} · {}]", format_duration(elapsed));
+  }
+
+  // Dimmed throughout, other than a failing status
+  let status = if status_code == 0 { status } else { format!("{}{DIM}", paint(&status, BOLD_RED)) };
+  format!("{DIM}[{status} · {}]{RESET}", format_duration(elapsed))
+}
+
+/// A duration, as compactly as is useful: "340ms", "2.41s", "3m 07s" or "1h 02m"
+pub fn
+format_duration (elapsed: Duration) -> String
+{
+  let secs = elapsed.as_secs();
+  match secs {
+    0 => format!("{}ms", elapsed.as_millis()),
+    1..=59 => format!("{:.2}s", elapsed.as_secs_f64()),
+    60..=3599 => format!("{}m {:02}s", secs / 60, secs % 60),
+    _ => format!("{}h {:02}m", secs / 3600, secs % 3600 / 60)
+  }
+}
+
+/// Lets the user know that a long-running command has finished, once it's taken at least `notify.after` seconds
+/// (by default, never). `notify.method` is `bell` to ring the terminal bell, or `desktop` for a desktop
+/// notification, through `notify-send` on Linux or `osascript` on macOS; the bell is rung should that fail.
+pub fn
+notify_if_long (command: &str, status_code: i32, elapsed: Duration, config: &Config, uname: &str)
+{
+  let after = config.get_usize("notify.after", 0);
+  if after == 0 || elapsed < Duration::from_secs(after as u64) {
+    return;
+  }
+
+  let message = format!("{} finished with exit {} after {}", command, status_code, format_duration(elapsed));
+  let notified = config.get("notify.method") == Some("desktop") && desktop_notification(&message, uname);
+  if !notified {
+    let mut stderr = io::stderr();
+    let _ = stderr.write_all(b"\x07");
+    let _ = stderr.flush();
+  }
+}
+
+/// Shows a desktop notification, returning whether it succeeded
+fn
+desktop_notification (message: &str, uname: &str) -> bool
+{
+  let mut command = if uname == "Darwin" {
+    let escaped = message.replace('\\', "\\\\").replace('"', "\\\"");
+    let mut command = Command::new("osascript");
+    command.arg("-e").arg(format!("display notification \"{escaped}\" with title \"nl-sh\""));
+    command
+  } else {
+    let mut command = Command::new("notify-send");
+    command.arg("nl-sh").arg(message);
+    command
+  };
+
+  command.output().is_ok_and(|output| output.status.success())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_format_duration () {
+    assert_eq!(format_duration(Duration::from_millis(340)), "340ms");
+    assert_eq!(format_duration(Duration::from_millis(2414)), "2.41s");
+    assert_eq!(format_duration(Duration::from_secs(187)), "3m 07s");
+    assert_eq!(format_duration(Duration::from_secs(3720)), "1h 02m");
+  }
+
+  #[test]
+  fn test_footer () {
+    assert_eq!(footer(0, Duration::from_millis(12), false), "[exit 0 · 12ms]");
+    assert_eq!(footer(2, Duration::from_secs(5), false), "[exit 2 · 5.00s]");
+    assert_eq!(footer(0, Duration::from_millis(12), true), "\x1b[2m[exit 0 · 12ms]\x1b[0m");
+    assert!(footer(1, Duration::from_millis(12), true).contains(&paint("exit 1", BOLD_RED)));
+  }
+}
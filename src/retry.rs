@@ -0,0 +1,220 @@
+use std::fmt;
+use std::future::Future;
+use std::time::{Duration, Instant};
+
+use crate::config::Config;
+use crate::runtime::{clear_partial, fragments_received};
+
+/// Default limit on how long a remote model may go without responding, in seconds
+const DEFAULT_TIMEOUT: u64 = 60;
+
+/// How often a request in flight is checked for progress
+const PROGRESS_CHECK: Duration = Duration::from_millis(50);
+
+/// Default number of attempts made for each request
+const DEFAULT_ATTEMPTS: usize = 3;
+
+/// Delay before the first retry; each subsequent retry waits twice as long as the last, up to `MAX_DELAY`
+const BASE_DELAY: Duration = Duration::from_secs(1);
+
+const MAX_DELAY: Duration = Duration::from_secs(30);
+
+/// Error types the Anthropic API reports for rate limiting and server-side failures, which the client library surfaces
+/// in its errors in place of status codes
+static RETRYABLE_ERROR_TYPES: &[&str] = &["rate_limit_error", "overloaded_error", "api_error"];
+
+/// An unsuccessful HTTP response from a model API
+#[derive(Debug)]
+pub struct HttpError {
+  pub status: u16,
+  pub body: String,
+}
+
+impl fmt::Display for HttpError
+{
+  fn fmt (&self, f: &mut fmt::Formatter) -> fmt::Result
+  {
+    write!(f, "HTTP status {}: {}", self.status, self.body.trim())
+  }
+}
+
+impl std::error::Error for HttpError {}
+
+/// How requests to a backend are bounded and retried. An attempt is abandoned once `timeout` passes without a response,
+/// either before the first streamed fragment or between two of them, so a slow model which is still answering isn't
+/// cut off. Rate-limited (429) and server (5xx) failures are retried with exponential backoff, up to `attempts` in
+/// total. Configured with `model.timeout` and `model.attempts`, either of which can be given per backend, as in
+/// `model.claude.timeout = 20`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RequestPolicy {
+  pub timeout: Duration,
+  pub attempts: usize,
+  pub base_delay: Duration,
+}
+
+impl RequestPolicy
+{
+  pub fn from_config (config: &Config, backend: &str) -> Self
+  {
+    let setting = |name: &str, default: usize| {
+      config.get_usize(&format!("model.{backend}.{name}"), config.get_usize(&format!("model.{name}"), default))
+    };
+
+    RequestPolicy {
+      timeout: Duration::from_secs(setting("timeout", DEFAULT_TIMEOUT as usize).max(1) as u64),
+      attempts: setting("attempts", DEFAULT_ATTEMPTS).max(1),
+      base_delay: BASE_DELAY,
+    }
+  }
+
+  /// Runs `request`, retrying it as this policy allows. `request` is called once per attempt.
+  pub async fn run<T, F, R> (&self, mut request: F) -> Result<T, Box<dyn std::error::Error>>
+    where F: FnMut() -> R, R: Future<Output = Result<T, Box<dyn std::error::Error>>>
+  {
+    let mut attempt = 0;
+    loop {
+      let error = match self.until_stalled(request()).await {
+        Some(Ok(response)) => return Ok(response),
+        Some(Err(e)) => e,
+        None => return Err(format!("request timed out after {:?} without a response", self.timeout).into())
+      };
+
+      attempt += 1;
+      if attempt >= self.attempts || !is_retryable(error.as_ref()) {
+        return Err(error);
+      }
+
+      // Discard anything streamed by the failed attempt
+      clear_partial();
+      tokio::time::sleep(backoff_delay(self.base_delay, attempt)).await;
+    }
+  }
+
+  /// Runs a single attempt, unless it goes `timeout` without completing or streaming a fragment, when it's dropped
+  async fn until_stalled<T> (&self, request: impl Future<Output = T>) -> Option<T>
+  {
+    tokio::pin!(request);
+    let mut fragments = fragments_received();
+    let mut last_progress = Instant::now();
+
+    loop {
+      tokio::select! {
+        biased;
+        result = &mut request => return Some(result),
+        _ = tokio::time::sleep(PROGRESS_CHECK.min(self.timeout)) => {
+          if fragments_received() != fragments {
+            fragments = fragments_received();
+            last_progress = Instant::now();
+          } else if last_progress.elapsed() >= self.timeout {
+            return None;
+          }
+        }
+      }
+    }
+  }
+}
+
+/// Whether a failed request is worth retrying: it was rate limited, or the server failed
+fn
+is_retryable (error: &(dyn std::error::Error + 'static)) -> bool
+{
+  if let Some(http) = error.downcast_ref::<HttpError>() {
+    return http.status == 429 || http.status >= 500;
+  }
+
+  let message = error.to_string();
+  RETRYABLE_ERROR_TYPES.iter().any(|error_type| message.contains(error_type))
+}
+
+/// The delay before the given retry, doubling with each one
+fn
+backoff_delay (base: Duration, retry: usize) -> Duration
+{
+  let factor = 2u32.saturating_pow(retry.saturating_sub(1) as u32);
+  base.saturating_mul(factor).min(MAX_DELAY)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::runtime::{runtime, show_partial};
+  use std::cell::Cell;
+
+  fn policy (attempts: usize) -> RequestPolicy
+  {
+    RequestPolicy { timeout: Duration::from_millis(200), attempts, base_delay: Duration::from_millis(1) }
+  }
+
+  fn http_error (status: u16) -> Box<dyn std::error::Error>
+  {
+    Box::new(HttpError { status, body: String::new() })
+  }
+
+  #[test]
+  fn test_backoff_delay () {
+    assert_eq!(backoff_delay(BASE_DELAY, 1), Duration::from_secs(1));
+    assert_eq!(backoff_delay(BASE_DELAY, 3), Duration::from_secs(4));
+    assert_eq!(backoff_delay(BASE_DELAY, 40), MAX_DELAY);
+  }
+
+  #[test]
+  fn test_is_retryable () {
+    assert!(is_retryable(http_error(429).as_ref()));
+    assert!(is_retryable(http_error(503).as_ref()));
+    assert!(!is_retryable(http_error(401).as_ref()));
+    assert!(is_retryable(Box::<dyn std::error::Error>::from("overloaded_error: Overloaded").as_ref()));
+    assert!(!is_retryable(Box::<dyn std::error::Error>::from("invalid_request_error").as_ref()));
+  }
+
+  #[test]
+  fn test_retries () {
+    let calls = Cell::new(0);
+    let result = runtime().block_on(policy(3).run(|| {
+      calls.set(calls.get() + 1);
+      let call = calls.get();
+      async move { if call < 3 { Err(http_error(429)) } else { Ok(call) } }
+    }));
+
+    assert_eq!(result.unwrap(), 3);
+  }
+
+  #[test]
+  fn test_gives_up () {
+    let calls = Cell::new(0);
+    let result = runtime().block_on(policy(3).run(|| {
+      calls.set(calls.get() + 1);
+      async { Err::<(), _>(http_error(401)) }
+    }));
+
+    assert!(result.is_err());
+    assert_eq!(calls.get(), 1);
+  }
+
+  #[test]
+  fn test_timeout () {
+    let result = runtime().block_on(policy(3).run(std::future::pending::<Result<(), Box<dyn std::error::Error>>>));
+    assert_eq!(result.unwrap_err().to_string(), "request timed out after 200ms without a response");
+  }
+
+  #[test]
+  fn test_streaming_outlasts_timeout () {
+    // Takes twice the timeout in all, but is never silent for as long as the timeout
+    let result = runtime().block_on(policy(1).run(|| async {
+      for _ in 0..8 {
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        show_partial("token ");
+      }
+      Ok::<_, Box<dyn std::error::Error>>(())
+    }));
+    assert!(result.is_ok());
+  }
+
+  #[test]
+  fn test_from_config () {
+    let config = Config::parse("model.timeout = 20\nmodel.claude.timeout = 5\nmodel.local.timeout = 0\nmodel.attempts = 0");
+    assert_eq!(RequestPolicy::from_config(&config, "claude").timeout, Duration::from_secs(5));
+    assert_eq!(RequestPolicy::from_config(&config, "gpt4").timeout, Duration::from_secs(20));
+    assert_eq!(RequestPolicy::from_config(&config, "local").timeout, Duration::from_secs(1));
+    assert_eq!(RequestPolicy::from_config(&config, "gpt4").attempts, 1);
+  }
+}
@@ -0,0 +1,188 @@
+use crate::highlight::PRIVILEGED;
+use crate::lexer::{is_assignment, split_commands, SimpleCommand};
+
+/// Commands which are risky however they're run, with the reason shown to the user
+static RISKY_COMMANDS: &[(&str, &str)] = &[("mkfs", "formats a disk"), ("fdisk", "partitions a disk"),
+  ("parted", "partitions a disk"), ("wipefs", "erases a disk"), ("shred", "destroys files"),
+  ("kill", "terminates processes"), ("pkill", "terminates processes"), ("killall", "terminates processes"),
+  ("shutdown", "shuts down the machine"), ("reboot", "restarts the machine"), ("halt", "shuts down the machine"),
+  ("poweroff", "shuts down the machine")];
+
+/// Shells which run a script piped to them, as in `curl ... | sh`
+static SHELLS: &[&str] = &["sh", "bash", "zsh", "dash", "ksh", "fish"];
+
+/// Commands which download from the internet
+static DOWNLOADERS: &[&str] = &["curl", "wget"];
+
+/// Commands which run the command that follows them, as in `xargs kill`
+pub static WRAPPERS: &[&str] = &["xargs", "env", "nohup", "nice", "time", "timeout"];
+
+/// Options of the prefixes above which take a separate argument, as in `nice -n 10` or `sudo -u root`
+static PREFIX_OPTIONS: &[(&str, &[&str])] = &[
+  ("sudo", &["-u", "-g", "-C", "-D", "-h", "-p", "-r", "-t", "-U", "--user", "--group", "--chdir", "--host", "--prompt",
+    "--role", "--type", "--other-user", "--close-from"]),
+  ("doas", &["-u", "-C"]), ("pkexec", &["--user"]), ("nice", &["-n", "--adjustment"]),
+  ("timeout", &["-s", "-k", "--signal", "--kill-after"]), ("env", &["-u", "-C", "-S", "--unset", "--chdir", "--split-string"]),
+  ("xargs", &["-a", "-d", "-E", "-I", "-L", "-n", "-P", "-s", "--arg-file", "--delimiter", "--max-args", "--max-procs"]),
+  ("time", &["-f", "-o", "--format", "--output"])];
+
+/// Why running a command line warrants a second look, if it does: it deletes files recursively, writes to disks,
+/// terminates processes, rewrites git history, runs a downloaded script or runs with elevated privileges. Commands
+/// are only ever flagged, never refused; the user decides.
+pub fn
+assess_risk (command: &str) -> Option<String>
+{
+  let commands = split_commands(command);
+  let mut reasons: Vec<&str> = Vec::new();
+  let mut names = Vec::new();
+
+  for simple in &commands {
+    let (privileged, words) = command_words(simple);
+    if privileged {
+      reasons.push("runs with elevated privileges");
+    }
+
+    if let Some(reason) = command_risk(&words) {
+      reasons.push(reason);
+    }
+    names.extend(words.first().copied());
+  }
+
+  if names.iter().any(|name| DOWNLOADERS.contains(name)) && names.iter().any(|name| SHELLS.contains(name)) {
+    reasons.push("runs a script downloaded from the internet");
+  }
+
+  // Each reason once, in the order first found
+  let mut unique: Vec<&str> = Vec::new();
+  for reason in reasons {
+    if !unique.contains(&reason) {
+      unique.push(reason);
+    }
+  }
+
+  if unique.is_empty() {
+    None
+  } else {
+    Some(unique.join(", "))
+  }
+}
+
+/// The words of the command a simple command really runs, without any prefix such as `sudo` or `xargs`; and whether
+/// it's run with elevated privileges
+fn
+command_words (simple: &SimpleCommand) -> (bool, Vec<&str>)
+{
+  let words: Vec<&str> = simple.words.iter().map(|word| word.text.as_str()).collect();
+  let (start, privileged) = command_start(&words);
+  (privileged, words[start..].to_vec())
+}
+
+/// Where the command a simple command really runs begins among its words: past any variable assignments, and any
+/// prefix such as `sudo` or `xargs` along with the prefix's options and their arguments. Also whether the command
+/// runs with elevated privileges.
+pub fn
+command_start (words: &[&str]) -> (usize, bool)
+{
+  let mut start = 0;
+  let mut privileged = false;
+
+  loop {
+    while words.get(start).is_some_and(|word| is_assignment(word)) {
+      start += 1;
+    }
+
+    let prefix = match words.get(start).copied() {
+      Some(word) if PRIVILEGED.contains(&word) || WRAPPERS.contains(&word) => word,
+      _ => return (start, privileged)
+    };
+    privileged |= PRIVILEGED.contains(&prefix);
+    start += 1;
+
+    // The prefix's own options, along with any arguments they take
+    let options = PREFIX_OPTIONS.iter().find(|(name, _)| *name == prefix).map_or(&[][..], |(_, options)| *options);
+    while let Some(option) = words.get(start).copied().filter(|word| word.starts_with('-')) {
+      start += 1;
+      if option == "--" {
+        break;
+      }
+      if options.contains(&option) && start < words.len() {
+        start += 1;
+      }
+    }
+
+    // `timeout` takes a duration before the command
+    if prefix == "timeout" && start < words.len() {
+      start += 1;
+    }
+  }
+}
+
+/// Why a single command is risky, if it is
+fn
+command_risk (words: &[&str]) -> Option<&'static str>
+{
+  let (name, args) = words.split_first()?;
+  let has_flag = |short: char, long: &str| args.iter().any(|arg| {
+    *arg == long || (arg.starts_with('-') && !arg.starts_with("--") && arg.contains(short))
+  });
+
+  match *name {
+    "rm" if has_flag('r', "--recursive") || has_flag('R', "--recursive") => Some("deletes files recursively"),
+    "dd" if args.iter().any(|arg| arg.starts_with("of=")) => Some("writes directly to a file or device"),
+    "chmod" | "chown" | "chgrp" if has_flag('R', "--recursive") => Some("changes permissions recursively"),
+    "git" => match args.first().copied() {
+      Some("push") if has_flag('f', "--force") || args.contains(&"--force-with-lease") => Some("overwrites remote git history"),
+      Some("reset") if args.contains(&"--hard") => Some("discards uncommitted changes"),
+      Some("clean") if has_flag('f', "--force") => Some("deletes untracked files"),
+      _ => None
+    },
+    name if name.starts_with("mkfs.") => Some("formats a disk"),
+    name => RISKY_COMMANDS.iter().find(|(command, _)| *command == name).map(|(_, reason)| *reason)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_assess_risk () {
+    assert_eq!(assess_risk("rm -rf build"), Some("deletes files recursively".to_string()));
+    assert_eq!(assess_risk("sudo rm -r /var/cache/x"), Some("runs with elevated privileges, deletes files recursively".to_string()));
+    assert_eq!(assess_risk("lsof -ti :8080 | xargs kill"), Some("terminates processes".to_string()));
+    assert_eq!(assess_risk("kill -9 1234; kill 5678"), Some("terminates processes".to_string()));
+    assert_eq!(assess_risk("curl -fsSL https://example.com/install.sh | sh"), Some("runs a script downloaded from the internet".to_string()));
+    assert_eq!(assess_risk("git push --force origin main"), Some("overwrites remote git history".to_string()));
+    assert_eq!(assess_risk("dd if=disk.img of=/dev/sdb bs=4M"), Some("writes directly to a file or device".to_string()));
+  }
+
+  #[test]
+  fn test_prefixed_commands () {
+    let recursive = Some("deletes files recursively".to_string());
+    assert_eq!(assess_risk("timeout 5 rm -rf x"), recursive);
+    assert_eq!(assess_risk("timeout -s KILL 5m rm -rf x"), recursive);
+    assert_eq!(assess_risk("nice -n 10 rm -rf /"), recursive);
+    assert_eq!(assess_risk("env FOO=1 rm -rf /"), recursive);
+    assert_eq!(assess_risk("env -u HOME BAR=2 nohup rm -rf /"), recursive);
+    assert_eq!(assess_risk("FOO=1 rm -rf /"), recursive);
+    assert_eq!(assess_risk("find . -name '*.o' | xargs -n 1 rm -rf"), recursive);
+    assert_eq!(assess_risk("sudo -u root rm -rf /"), Some("runs with elevated privileges, deletes files recursively".to_string()));
+    assert_eq!(assess_risk("sudo -- rm -rf /"), Some("runs with elevated privileges, deletes files recursively".to_string()));
+  }
+
+  #[test]
+  fn test_command_start () {
+    assert_eq!(command_start(&["ls", "-la"]), (0, false));
+    assert_eq!(command_start(&["FOO=1", "ls"]), (1, false));
+    assert_eq!(command_start(&["sudo", "-u", "root", "rm", "x"]), (3, true));
+    assert_eq!(command_start(&["nice", "-n", "10", "env", "A=1", "make"]), (5, false));
+    assert_eq!(command_start(&["sudo"]), (1, true));
+  }
+
+  #[test]
+  fn test_safe_commands () {
+    for command in ["ls -la", "rm notes.txt", "git push origin main", "python3 -m venv .venv", "grep -r kill src"] {
+      assert_eq!(assess_risk(command), None, "{command}");
+    }
+  }
+}
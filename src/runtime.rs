@@ -0,0 +1,222 @@
+use std::fmt;
+use std::future::Future;
+use std::io::{self, IsTerminal, Write};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::thread;
+use std::time::Duration;
+
+use crossterm::event::{self, Event, KeyCode, KeyModifiers};
+use crossterm::terminal;
+use tokio::runtime::Runtime;
+
+/// Frames of the spinner shown while waiting on a model
+static SPINNER: &[char] = &['⠋', '⠙', '⠹', '⠸', '⠼', '⠴', '⠦', '⠧', '⠇', '⠏'];
+
+/// How often the spinner advances, and keypresses are checked for
+const TICK: Duration = Duration::from_millis(80);
+
+static RUNTIME: OnceLock<Runtime> = OnceLock::new();
+
+/// Set when the user cancels the request in flight
+static CANCELLED: AtomicBool = AtomicBool::new(false);
+
+/// The response of the request in flight so far, for models which stream their responses
+static PARTIAL: Mutex<String> = Mutex::new(String::new());
+
+/// How many fragments of streamed responses have arrived, so that a request which is still streaming can be told
+/// apart from one which has stalled
+static FRAGMENTS: AtomicUsize = AtomicUsize::new(0);
+
+/// Returned in place of a response when the user cancels a request
+#[derive(Debug)]
+pub struct Cancelled;
+
+impl fmt::Display for Cancelled
+{
+  fn fmt (&self, f: &mut fmt::Formatter) -> fmt::Result
+  {
+    write!(f, "request cancelled")
+  }
+}
+
+impl std::error::Error for Cancelled {}
+
+/// The runtime shared by all model requests for the life of the shell
+pub fn
+runtime () -> &'static Runtime
+{
+  RUNTIME.get_or_init(|| Runtime::new().expect("failed to start async runtime"))
+}
+
+/// Whether the user has cancelled the request in flight. Work which can't simply be dropped, such as inference
+/// by a local model, checks this to stop early.
+pub fn
+cancelled () -> bool
+{
+  CANCELLED.load(Ordering::SeqCst)
+}
+
+/// Adds a fragment of a streamed response, which is drawn beside the spinner in place of its message until the
+/// request completes
+pub fn
+show_partial (text: &str)
+{
+  FRAGMENTS.fetch_add(1, Ordering::SeqCst);
+  if let Ok(mut partial) = PARTIAL.lock() {
+    partial.push_str(text);
+  }
+}
+
+/// The number of fragments of streamed responses received by this process so far
+pub fn
+fragments_received () -> usize
+{
+  FRAGMENTS.load(Ordering::SeqCst)
+}
+
+/// Discards the streamed response so far, such as when a request is retried
+pub fn
+clear_partial ()
+{
+  if let Ok(mut partial) = PARTIAL.lock() {
+    partial.clear();
+  }
+}
+
+/// Runs a model request to completion on the shared runtime, showing `message` beside a spinner meanwhile. Pressing
+/// Esc or Ctrl-C abandons the request, returning `Cancelled`, so that the user is returned to the prompt rather than
+/// the shell exiting.
+pub fn
+wait_for<T> (message: &str, request: impl Future<Output = Result<T, Box<dyn std::error::Error>>>) -> Result<T, Box<dyn std::error::Error>>
+{
+  CANCELLED.store(false, Ordering::SeqCst);
+  clear_partial();
+
+  // Keypresses can only be watched for, and the spinner drawn, when attached to a terminal
+  let done = Arc::new(AtomicBool::new(false));
+  let watcher = if io::stdin().is_terminal() && io::stderr().is_terminal() {
+    let message = message.to_string();
+    let done = done.clone();
+    Some(thread::spawn(move || watch(&message, &done)))
+  } else {
+    None
+  };
+
+  let result = runtime().block_on(async {
+    tokio::select! {
+      biased;
+      result = request => result,
+      _ = until_cancelled() => Err(Box::new(Cancelled) as Box<dyn std::error::Error>),
+    }
+  });
+
+  done.store(true, Ordering::SeqCst);
+  if let Some(watcher) = watcher {
+    let _ = watcher.join();
+  }
+
+  result
+}
+
+/// Resolves once the user cancels, either by a keypress seen by `watch`, or by SIGINT when not attached to a terminal
+async fn
+until_cancelled ()
+{
+  let flagged = async {
+    while !cancelled() {
+      tokio::time::sleep(TICK).await;
+    }
+  };
+
+  tokio::select! {
+    _ = flagged => {},
+    Ok(()) = tokio::signal::ctrl_c() => CANCELLED.store(true, Ordering::SeqCst),
+  }
+}
+
+/// Draws the spinner until `done`, alongside the response so far if it's being streamed, and watches for Esc or Ctrl-C. The terminal is put into raw mode meanwhile, so that
+/// keypresses are seen immediately and Ctrl-C arrives as a key rather than a signal.
+fn
+watch (message: &str, done: &AtomicBool)
+{
+  let raw = terminal::enable_raw_mode().is_ok();
+  let mut stderr = io::stderr();
+
+  for frame in SPINNER.iter().cycle() {
+    if done.load(Ordering::SeqCst) {
+      break;
+    }
+
+    let partial = PARTIAL.lock().map(|partial| partial.clone()).unwrap_or_default();
+    let width = terminal::size().map(|(columns, _)| columns as usize).unwrap_or(80);
+    let text = if partial.trim().is_empty() { message } else { partial.trim() };
+
+    let _ = write!(stderr, "\r\x1b[2K{frame} {}", tail(text, width.saturating_sub(3)));
+    let _ = stderr.flush();
+
+    if raw && event::poll(TICK).unwrap_or(false) {
+      if let Ok(Event::Key(key)) = event::read() {
+        let interrupt = key.code == KeyCode::Char('c') && key.modifiers.contains(KeyModifiers::CONTROL);
+        if key.code == KeyCode::Esc || interrupt {
+          CANCELLED.store(true, Ordering::SeqCst);
+        }
+      }
+    } else if !raw {
+      thread::sleep(TICK);
+    }
+  }
+
+  if raw {
+    let _ = terminal::disable_raw_mode();
+  }
+
+  // Erase the spinner line
+  let _ = write!(stderr, "\r\x1b[2K");
+  let _ = stderr.flush();
+}
+
+/// The end of the last line of `text`, at most `max_chars` characters; so the latest part of a long response stays
+/// visible on a single line
+fn
+tail (text: &str, max_chars: usize) -> &str
+{
+  let line = text.lines().last().unwrap_or("");
+  let skip = line.chars().count().saturating_sub(max_chars);
+  match line.char_indices().nth(skip) {
+    Some((index, _)) => &line[index..],
+    None => ""
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_wait_for () {
+    assert_eq!(wait_for("thinking", async { Ok(7) }).unwrap(), 7);
+    assert!(wait_for::<()>("thinking", async { Err("failed".into()) }).is_err());
+  }
+
+  #[test]
+  fn test_tail () {
+    assert_eq!(tail("ls -la", 10), "ls -la");
+    assert_eq!(tail("find . -name '*.rs'", 6), "'*.rs'");
+    assert_eq!(tail("Here it is:\n```bash\nls", 10), "ls");
+    assert_eq!(tail("", 10), "");
+  }
+
+  #[test]
+  fn test_wait_for_cancelled () {
+    // Simulates a keypress, repeatedly, since other tests may reset the flag meanwhile
+    let result = wait_for::<()>("thinking", async {
+      loop {
+        CANCELLED.store(true, Ordering::SeqCst);
+        tokio::time::sleep(TICK).await;
+      }
+    });
+
+    assert!(result.unwrap_err().is::<Cancelled>());
+  }
+}
@@ -1,56 +1,460 @@
 use inquire::{Confirm, Text};
 use inquire::error::InquireError;
 use inquire::history::SimpleHistory;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::io::{self, Write};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
 pub use crate::command::*;
 pub use crate::context::*;
 pub use crate::model::*;
+use crate::builtin::{Builtins, LastRun, NextAction, Session};
+use crate::classifier::Classifier;
+use crate::color::color_hints;
+use crate::completion::{Completer, CompletionCache};
+use crate::highlight::{colors_enabled, highlight_command, highlight_input, paint, BOLD_YELLOW};
+use crate::pager::show_output;
+use crate::prompt::{render_prompt, PromptInfo, DEFAULT_PROMPT};
+use crate::report::{footer, notify_if_long};
+use crate::risk::assess_risk;
+use crate::lexer::split_commands;
+use crate::typo::correct_command_line;
+use crate::providers::ContextProviders;
 
 #[cfg(test)]
 use mockall::predicate::*;
 
-/// Some POSIX commands have verb-y characteristics, and for these, we'll let the LLM determine
-/// whether the user intent is to run a specfic command, or whether the request is something
-/// akin to "diff these two files" or "sort and print the text in a, b, and c"
-static COMMAND_EXCEPTIONS: &[&str] = &["alias", "cat", "diff", "expand", "find", "kill", "link", 
-  "list", "log", "print", "read", "sort", "split", "strip", "touch", "type", "what", "which", "who"];
-
-/// Determine whether the input from the prompt is a likely system command. This is
-/// a best-effort attempt to short-circut requests to an LLM, to improve performance
-/// for the most obvious native command interpreted by the prompt.
-/// 
-/// In the case where the user request is ambiguous, as defined by the set 
-/// of COMMAND_EXCEPTIONS strings, fall through and indicate to pass the user input
-/// to the LLM to rationalize.
-fn 
-likely_system_command (context: &Context, command: &String, executor: &dyn CommandExecutorInterface) -> bool 
+/// Commands whose purpose is to find or list things; given no output, they most likely looked in the wrong place
+static SEARCH_COMMANDS: &[&str] = &["find", "grep", "egrep", "fgrep", "rg", "ag", "ack", "locate", "mdfind", "fd", "ls",
+  "which", "whereis"];
+
+/// Words opening a request for information, as in "find every TODO in this project" or "which files are largest"
+static QUERY_WORDS: &[&str] = &["find", "list", "show", "search", "which", "what", "where", "who", "how", "count",
+  "get", "display", "print", "locate", "look"];
+
+/// What the model is told when a search finds nothing
+const EMPTY_OUTPUT_COMPLAINT: &str = "the command printed nothing, but I expected it to find something";
+
+/// Whether a command of the given name exists: one of the user's aliases, or anything the shell can find
+fn
+command_exists (context: &Context, name: &str, executor: &dyn CommandExecutorInterface) -> bool
+{
+  context.aliases.contains(name) || executor.exists(&context.shell, name, &context.env)
+}
+
+/// Classifies a line entered at the prompt, remembering the answers: the line is classified when it's echoed
+/// highlighted, and again when deciding how to run it, and each command name is looked up when highlighting it and
+/// when classifying. Meant to last for one line, since commands may come and go.
+struct LineClassifier<'a> {
+  context: &'a Context,
+  executor: &'a dyn CommandExecutorInterface,
+  classifier: &'a Classifier,
+  exists: RefCell<HashMap<String, bool>>,
+  commands: RefCell<HashMap<String, bool>>,
+}
+
+impl<'a> LineClassifier<'a> {
+  fn new (context: &'a Context, executor: &'a dyn CommandExecutorInterface, classifier: &'a Classifier) -> Self
+  {
+    LineClassifier { context, executor, classifier, exists: RefCell::default(), commands: RefCell::default() }
+  }
+
+  /// As `command_exists`
+  fn exists (&self, name: &str) -> bool
+  {
+    if let Some(&exists) = self.exists.borrow().get(name) {
+      return exists;
+    }
+
+    let exists = command_exists(self.context, name, self.executor);
+    self.exists.borrow_mut().insert(name.to_string(), exists);
+    exists
+  }
+
+  /// As `likely_system_command`
+  fn is_command (&self, command: &str) -> bool
+  {
+    if let Some(&is_command) = self.commands.borrow().get(command) {
+      return is_command;
+    }
+
+    let is_command = self.classifier.is_command(command, &|name| self.exists(name));
+    self.commands.borrow_mut().insert(command.to_string(), is_command);
+    is_command
+  }
+}
+
+/// Highlights input echoed at the prompt, once entered, to show how it will be treated: as a command, with any
+/// missing command names marked, or as a request for the model
+fn
+highlight_prompt_input (input: &str, line: &LineClassifier) -> String
+{
+  if !colors_enabled() || Builtins::is_builtin(input) {
+    return input.to_string();
+  }
+
+  let exists = |name: &str| line.exists(name);
+  match parse_intent(input) {
+    Intent::Literal(_) => {
+      let (prefix, command) = input.split_at(input.find('!').map_or(0, |bang| bang + 1));
+      format!("{}{}", prefix, highlight_command(command, &exists))
+    },
+    Intent::Model(_) => highlight_input(input, false, &exists),
+    Intent::Detect(command) => highlight_input(input, line.is_command(&command), &exists)
+  }
+}
+
+/// Highlights a proposed command for the confirmation prompt
+fn
+highlight_proposed_command (context: &Context, command: &str, executor: &dyn CommandExecutorInterface) -> String
+{
+  if !colors_enabled() {
+    return command.to_string();
+  }
+
+  highlight_command(command, &|name| command_exists(context, name, executor))
+}
+
+/// Determine whether the input from the prompt is a likely system command, using the classifier. The user's own
+/// aliases are always commands, as is anything the shell can find via `command -v`.
+fn
+likely_system_command (context: &Context, command: &str, executor: &dyn CommandExecutorInterface,
+  classifier: &Classifier) -> bool
+{
+  classifier.is_command(command, &|name| command_exists(context, name, executor))
+}
+
+/// Corrects a mistyped command name, such as `gti status`, without consulting the model. The command name must not
+/// exist, and the correction must be confident and result in a likely system command. Candidates are the executables
+/// on `$PATH`, the user's aliases and the commands in their history.
+fn
+correct_typo (context: &Context, input: &str, executor: &dyn CommandExecutorInterface, classifier: &Classifier,
+  completion: &CompletionCache) -> Option<String>
+{
+  let name = split_commands(input).first()?.name()?.text.to_lowercase();
+  if command_exists(context, &name, executor) {
+    return None;
+  }
+
+  let mut candidates = context.env.get("PATH").map(|path| completion.executables(&path).to_vec()).unwrap_or_default();
+  candidates.extend(context.aliases.table.keys().cloned());
+  candidates.extend(context.get_command_history().iter()
+    .filter_map(|line| split_commands(line).first().and_then(|command| command.name()).map(|name| name.text.clone())));
+  candidates.sort();
+  candidates.dedup();
+
+  correct_command_line(input, &candidates)
+    .filter(|corrected| likely_system_command(context, corrected, executor, classifier))
+}
+
+/// Shows everything a command wrote, stdout and then stderr, followed by a footer with its exit status and how long it
+/// took to run. Stdout keeps its colours, and is paged if it doesn't fit on the screen; binary data on either stream
+/// is summarised rather than written to the terminal.
+fn
+report_command (context: &Context, cmd: &str, output: &CommandOutput, elapsed: Option<Duration>)
 {
-  let parts: Vec<&str> = command.split_whitespace().collect();
-  let cmd = parts.get(0).unwrap_or(&"").to_lowercase(); // Extract the just command without arguments
+  if output.binary {
+    println!("\n({})", output.stdout);
+  } else if let Err(e) = show_output(&output.raw_stdout, cmd, &context.config, &context.shell, &context.env) {
+    println!("error: {}", e);
+  }
+
+  if !output.raw_stderr.is_empty() {
+    let mut stderr = io::stderr();
+    let _ = if is_binary(&output.raw_stderr) {
+      writeln!(stderr, "({})", output.stderr)
+    } else {
+      stderr.write_all(&output.raw_stderr)
+    };
+    let _ = stderr.flush();
+  }
+
+  if let Some(elapsed) = elapsed {
+    if context.config.get_bool("output.footer", true) {
+      println!("{}", footer(output.status_code, elapsed, colors_enabled()));
+    }
+    notify_if_long(cmd, output.status_code, elapsed, &context.config, &context.uname);
+  }
+}
+
+/// Whether a model-suggested command succeeded but printed nothing when its request expected results; a search with
+/// the wrong predicates, say. Only checked with the `fix.empty_output` setting enabled.
+fn
+suspicious_empty_output (config: &Config, request: &str, command: &str, output: &CommandOutput) -> bool
+{
+  if !config.get_bool("fix.empty_output", false) || request == command {
+    return false;
+  }
 
-  if COMMAND_EXCEPTIONS.contains(&cmd.as_str()) {
-    // The user string contains a verb-y command, let the LLM sort it out
-    return false
+  if !output.success || !output.stdout.trim().is_empty() || !output.stderr.trim().is_empty() {
+    return false;
+  }
+
+  // Either the request asks for something, or the last command of a pipeline searches for something
+  let query = request.split_whitespace().next()
+    .is_some_and(|word| QUERY_WORDS.contains(&word.to_lowercase().as_str()));
+  let search = split_commands(command).last()
+    .and_then(|simple| simple.name().map(|name| name.text.clone()))
+    .is_some_and(|name| SEARCH_COMMANDS.contains(&name.as_str()));
+  query || search
+}
+
+/// How the user asked for their input to be treated
+#[derive(Debug, PartialEq)]
+enum Intent {
+  /// Run as a system command, exactly as given
+  Literal(String),
+  /// Send to the model
+  Model(String),
+  /// Decide with `likely_system_command`
+  Detect(String),
+}
+
+/// Interprets the prefixes which override `likely_system_command`: a leading `!` runs the rest of the input literally,
+/// and a leading `?` sends it to the model. Only the first `!` is removed, so `!! cmd` runs the negated `! cmd`.
+fn
+parse_intent (input: &str) -> Intent
+{
+  let input = input.trim();
+  if let Some(command) = input.strip_prefix('!') {
+    Intent::Literal(command.trim().to_string())
+  } else if let Some(request) = input.strip_prefix('?') {
+    Intent::Model(request.trim().to_string())
   } else {
-    // Determine whether the first word in the user input is valid command on this system, 
-    // via $SHELL command -v <cmd>
-    executor.exists(&context.shell, &cmd)
+    Intent::Detect(input.to_string())
+  }
+}
+
+/// Confirms with the user that they'd like to run a proposed command. Risky commands, such as `rm -rf`, are marked
+/// with the reason, and aren't run should the user simply press Enter.
+fn
+confirm_command (context: &Context, cmd: &str, executor: &dyn CommandExecutorInterface) -> Result<bool, InquireError>
+{
+  let proposed = highlight_proposed_command(context, cmd, executor);
+  match assess_risk(cmd) {
+    Some(reason) => {
+      let warning = format!("warning: this command {reason}");
+      println!("{}", if colors_enabled() { paint(&warning, BOLD_YELLOW) } else { warning });
+      Confirm::new(&proposed)
+        .with_default(false)
+        .with_help_message("execute this command anyway?")
+        .prompt()
+    },
+    None => Confirm::new(&proposed)
+      .with_default(true)
+      .with_help_message("execute this command?")
+      .prompt()
   }
 }
 
+/// Executes a confirmed command string on the system, with aliases expanded, and shows its output. Commands consisting
+/// only of directory builtins are applied to the context directly, since the underlying shell can't retain their
+/// effects; they aren't timed.
+fn
+execute_command (context: &mut Context, executor: &dyn CommandExecutorInterface, cmd: &str)
+  -> Result<(CommandOutput, Option<Duration>), Box<dyn std::error::Error>>
+{
+  let expanded = context.aliases.expand(cmd);
+  let (output, elapsed) = if is_directory_command(&expanded) {
+    (context.change_directory(cmd, &expanded)?, None)
+  } else {
+    // Commands run through pipes, so they're asked to colour their output as they would on a terminal
+    let hinted = if colors_enabled() && context.config.get_bool("color.output", true) {
+      color_hints(&expanded, context.system.coreutils, &context.env)
+    } else {
+      expanded.clone()
+    };
+
+    let started = Instant::now();
+    let output = executor.execute(&context.shell, &hinted, &context.env)?;
+    let elapsed = started.elapsed();
+    if output.success {
+      // Update the context state based on the issued command
+      context.update(cmd, &expanded)?;
+    }
+    (output, Some(elapsed))
+  };
+
+  report_command(context, cmd, &output, elapsed);
+  Ok((output, elapsed))
+}
+
+/// What the shell loop does once a command has been run
+enum Flow {
+  /// Prompt for the next input
+  Continue,
+  /// Leave the shell, as the user cancelled a confirmation
+  Exit,
+}
+
+/// Runs the command for the user's input, once confirmed, recording how it turned out in `last_run`. Should it fail,
+/// the model is asked to correct it. With `fix.empty_output` set, a search which succeeds without any output is
+/// offered back to the model for revision too.
+fn
+run_command (context: &mut Context, model: &dyn Model, executor: &dyn CommandExecutorInterface, input: &str, mut cmd: String,
+  skip_confirm: bool, last_run: &mut Option<LastRun>) -> Result<Flow, Box<dyn std::error::Error>>
+{
+  // The following runs in a simple loop, allowing for a single retry of a failed system command, by requesting a
+  // command correction from the model given context about the command objective and failure output.
+  for i in 0..3 {
+    let confirm = if input.eq(&cmd) && skip_confirm {
+      // If the input from the user is identical to the command to execute, just execute it without 
+      // asking for confirmation from the shell user.
+      Ok(true) 
+    } else if cmd.trim().is_empty() {
+      // If the command string is empty, this means the model didn't consider the input to be a sensible
+      // shell command.
+      println!("\ncould not interpret request");
+      break;
+    } else {
+      if i == 0 {
+        println!();
+      }
+
+      // Confirm with the user that they would like to execute the command
+      confirm_command(context, &cmd, executor)
+    };
+
+    match confirm {
+      Ok(true) => {
+        let (output, _) = execute_command(context, executor, &cmd)?;
+        *last_run = Some(LastRun { request: input.to_string(), command: cmd.clone(), output: output.clone() });
+
+        if output.success {
+          // A search which found nothing may well have been the wrong search
+          let revise = i < 2 && suspicious_empty_output(&context.config, input, &cmd, &output) && Confirm::new("no output")
+            .with_default(false)
+            .with_help_message("ask the model for another command?")
+            .prompt()
+            .unwrap_or(false);
+          if !revise {
+            break;
+          }
+
+          cmd = match wait_for("thinking", model.revise_command(context, input, &cmd, &output, EMPTY_OUTPUT_COMPLAINT)) {
+            Ok(cmd) => cmd,
+            Err(e) => {
+              println!("model request failed: {}", e);
+              break;
+            }
+          };
+        } else {
+          println!("Executed [{}] and failed with exit status {}", cmd, output.status_code);
+          if i < 2 {
+            println!("Retrying command formulation...");
+            cmd = match wait_for("thinking", model.attempt_correction(context, input, &cmd, &output)) {
+              Ok(cmd) => cmd,
+              Err(e) => {
+                println!("model request failed: {}", e);
+                break;
+              }
+            };
+          }
+        }
+      },
+      Ok(false) => {
+        println!("Aborting command");
+        break;
+      },
+      Err(e) if matches!(e, InquireError::OperationCanceled) || matches!(e, InquireError::OperationInterrupted) => {
+        println!("exiting");
+        return Ok(Flow::Exit);
+      },
+      Err(e) => {
+        println!("error: {}", e);
+        return Ok(Flow::Exit);
+      }
+    }
+  }
+
+  Ok(Flow::Continue)
+}
+
+/// Works towards a goal for `:do`: the model proposes one command at a time, each confirmed as usual and its output
+/// shown to the model before it proposes the next, until it declares the goal done. The model may take at most
+/// `agent.max_steps` steps, and every step is confirmed, even for input which would otherwise run without asking. A
+/// transcript of the steps is shown at the end.
+fn
+run_agent (context: &mut Context, model: &dyn Model, executor: &dyn CommandExecutorInterface, providers: &mut ContextProviders,
+  goal: &str, last_run: &mut Option<LastRun>) -> Result<(), Box<dyn std::error::Error>>
+{
+  let max_steps = context.config.get_usize("agent.max_steps", DEFAULT_MAX_STEPS);
+  let mut steps: Vec<Step> = Vec::new();
+
+  let outcome = loop {
+    if steps.len() >= max_steps {
+      break format!("stopped after {max_steps} steps without reaching the goal; see agent.max_steps");
+    }
+
+    // Commands may well change the environment, such as the working directory or the files in it
+    context.details = providers.gather(context, executor);
+    let reply = match wait_for("thinking", model.next_step(context, goal, &steps)) {
+      Ok(reply) => reply,
+      Err(e) => break format!("model request failed: {e}")
+    };
+
+    let cmd = match parse_step_reply(&reply) {
+      StepReply::Done(summary) if summary.is_empty() => break "done".to_string(),
+      StepReply::Done(summary) => break summary,
+      StepReply::Command(cmd) => cmd
+    };
+
+    println!("\nstep {} of at most {}", steps.len() + 1, max_steps);
+    match confirm_command(context, &cmd, executor) {
+      Ok(true) => {},
+      Ok(false) => break "stopped, as the last proposed command was declined".to_string(),
+      Err(e) if matches!(e, InquireError::OperationCanceled) || matches!(e, InquireError::OperationInterrupted) => {
+        break "cancelled".to_string();
+      },
+      Err(e) => break format!("error: {e}")
+    }
+
+    let (output, elapsed) = execute_command(context, executor, &cmd)?;
+    *last_run = Some(LastRun { request: goal.to_string(), command: cmd.clone(), output: output.clone() });
+    steps.push(Step { command: cmd, output, elapsed });
+  };
+
+  println!("\n{}", transcript(goal, &steps, &outcome));
+  Ok(())
+}
+
 /// Main shell UI loop. Collects input from the user, conditionally consults LLMs depending on the user prompt, executes
 /// subsequent commands and updates shell state.
 pub fn
-shell_loop (context: &mut Context, model: Box<dyn Model>, executor: &dyn CommandExecutorInterface) -> Result<(), Box<dyn std::error::Error>>
+shell_loop (context: &mut Context, mut model: Box<dyn Model>, executor: &dyn CommandExecutorInterface, usage: &Arc<Mutex<UsageLedger>>) -> Result<(), Box<dyn std::error::Error>>
 {
+  let mut providers = ContextProviders::from_config(&context.config);
+  let builtins = Builtins::new();
+  let classifier = Classifier::from_config(&context.config);
+  let completion = CompletionCache::default();
+  let mut last_run: Option<LastRun> = None;
+
   loop {
-    // Define the prompt prefix string, something like
+    // Render the prompt from the `prompt` template, by default something like
     // [nl-sh] /Users/mike $
-    let terminal_prompt = std::format!("[nl-sh] {} $", context.pwd.as_str());
+    // In offline mode, this is always preceded by [offline]
+    let template = context.config.get("prompt").unwrap_or(DEFAULT_PROMPT).to_string();
+    let info = PromptInfo {
+      offline: context.config.get_bool("offline", false),
+      pwd: context.pwd.clone(),
+      home: context.env.get("HOME").unwrap_or_default(),
+      user: context.env.get("USER").unwrap_or_default(),
+      status: last_run.as_ref().map(|run| run.output.status_code),
+      model: model.name(),
+      cost: usage.lock().unwrap().session_cost(),
+      ..PromptInfo::default()
+    };
+    let terminal_prompt = render_prompt(&template, &info.gather(&template), colors_enabled());
 
     // Collect the user input from the prompt; update the prompt history from the context
+    let line = LineClassifier::new(context, executor, &classifier);
+    let format_input = |input: &str| highlight_prompt_input(input, &line);
     let input = Text::new(&terminal_prompt)
       .with_history(SimpleHistory::new(context.get_command_history()))
+      .with_autocomplete(Completer::new(context, &builtins, &completion))
+      .with_formatter(&format_input)
       .prompt();
 
     match input {
@@ -59,75 +463,82 @@ shell_loop (context: &mut Context, model: Box<dyn Model>, executor: &dyn Command
           continue;
         }
 
+        // Builtin commands, such as `:help`, take precedence over everything else
+        if Builtins::is_builtin(&input) {
+          let mut next = None;
+          let mut session = Session { context, model: &mut model, executor, providers: &mut providers, usage,
+            last_run: &last_run, next: &mut next };
+          match builtins.dispatch(&input, &mut session) {
+            Ok(output) if output.is_empty() => {},
+            Ok(output) => println!("\n{}", output),
+            Err(e) => println!("\n{}", e)
+          }
+
+          // A builtin may hand back commands to confirm and run, such as another command for the last request
+          match next {
+            Some(NextAction::Command(cmd)) => {
+              let request = last_run.as_ref().map(|run| run.request.clone()).unwrap_or_else(|| cmd.clone());
+              if let Flow::Exit = run_command(context, model.as_ref(), executor, &request, cmd, false, &mut last_run)? {
+                return Ok(());
+              }
+            },
+            Some(NextAction::Goal(goal)) => run_agent(context, model.as_ref(), executor, &mut providers, &goal, &mut last_run)?,
+            None => {}
+          }
+          continue;
+        }
+
         // If the input is a likely and unambiguous system command, we'll take the text as-is and exec it through the shell.
         // Otherwise, we'll pass the input to the model and let the LLM sort it out. If it is, in fact, a valid
-        // command and argument, the model should return the input string.
-        let system_command = likely_system_command(context, &input, executor);
-        let mut cmd = if system_command {
-          input.clone() 
-        } else {
-          // Fetch input rationalization from the model
-          model.ask_model(context, &input)?
+        // command and argument, the model should return the input string. The user can override this guess with a
+        // `!` or `?` prefix.
+        let intent = parse_intent(&input);
+        let (input, system_command) = match &intent {
+          Intent::Literal(command) => (command.clone(), true),
+          Intent::Model(request) => (request.clone(), false),
+          Intent::Detect(input) => (input.clone(), line.is_command(input))
         };
 
-        // The following runs in a simple loop, allowing for a single retry of a failed system command, by requesting a
-        // command correction from the model given context about the command objective and failure output.
-        for i in 0..3 {
-          let confirm = if input.eq(&cmd) {
-            // If the input from the user is identical to the command to execute, just execute it without 
-            // asking for confirmation from the shell user.
-            Ok(true) 
-          } else if cmd.trim().is_empty() {
-            // If the command string is empty, this means the model didn't consider the input to be a sensible
-            // shell command.
-            println!("\ncould not interpret request");
-            continue;
-          } else {
-            if i == 0 {
-              print!("\n");
-            }
-
-            // Confirm with the user that they would like to execute the command
-            Confirm::new(&cmd)
-              .with_default(true)
-              .with_help_message("execute this command?")
-              .prompt()
-          };
+        if input.is_empty() {
+          continue;
+        }
 
-          match confirm {
-            Ok(true) => {
-              // Execute the confirmed command string on the system
-              let output = executor.execute(&context.shell, &cmd)?;
+        // Unless the user explicitly asked for literal execution, the always-confirm mode guards against any input
+        // being run without confirmation because it was misclassified as a command
+        let skip_confirm = matches!(intent, Intent::Literal(_)) || !context.config.get_bool("confirm.always", false);
 
-              if output.success {
-                // If successful, emit the stdout captured by the command
-                print!("\n{}", output.stdout);
+        // A mistyped command name is corrected locally when there's a confident fix, sparing a request to the model
+        let correction = match intent {
+          Intent::Detect(_) if !system_command => correct_typo(context, &input, executor, &classifier, &completion),
+          _ => None
+        };
 
-                // Update the context state based on the issued command
-                context.update(&cmd)?;
-                break;
-              } else {
-                println!("Executed [{}] and got error: {}", cmd, output.stderr);
-                if i < 2 {
-                  println!("Retrying command formulation...");
-                  cmd = model.attempt_correction(context, &input.as_str(), &cmd, &output)?;
-                }
+        let cmd = if let Some(corrected) = correction {
+          corrected
+        } else if system_command {
+          input.clone() 
+        } else {
+          // Fetch input rationalization from the model, along with up-to-date details of the environment
+          context.details = providers.gather(context, executor);
+          match wait_for("thinking", model.ask_model(context, &input)) {
+            Ok(cmd) => {
+              // Note which backend answered, when the primary one didn't
+              if model.used_fallback() {
+                println!("\n(answered by {})", model.answered_by());
               }
-            },
-            Ok(false) => {
-              println!("Aborting command");
-              break;
-            },
-            Err(e) if matches!(e, InquireError::OperationCanceled) || matches!(e, InquireError::OperationInterrupted) => {
-              println!("exiting");
-              return Ok(());
+              cmd
             },
             Err(e) => {
-              println!("error: {}", e);
-              return Ok(());
+              // Such as an exceeded usage budget, or the user cancelling; they can still run commands directly
+              println!("\nmodel request failed: {}", e);
+              continue;
             }
           }
-      }
+        };
+
+        if let Flow::Exit = run_command(context, model.as_ref(), executor, &input, cmd, skip_confirm, &mut last_run)? {
+          return Ok(());
+        }
     }, // Ok(input)
     Err(e) if matches!(e, InquireError::OperationCanceled) || matches!(e, InquireError::OperationInterrupted) => {
       // This was a ^C or esc
@@ -147,40 +558,96 @@ mod tests
 {
   use super::*;
 
-  fn get_test_context () -> Context 
+  #[test]
+  fn test_likely_system_command () 
   {
-    Context {
-      uname: "Darwin".to_string(),
-      shell: "/bin/zsh".to_string(),
-      os: "Darwin 23.3.0 arm64".to_string(),
-      pwd: "/home".to_string(),
-      history: CommandHistory::init("/bin/zsh", false).unwrap(),
-    }
+    let mut mock_executor = MockCommandExecutorInterface::new();
+    mock_executor.expect_exists()
+      .with(eq("/bin/zsh"), eq("ls"), always())
+      .returning(|_, _, _| true);
+
+    let context = Context::for_test("/home");
+
+    assert!(likely_system_command(&context, "ls", &mock_executor, &Classifier::new()));
   }
 
   #[test]
-  fn test_likely_system_command () 
+  fn test_not_likely_system_command () 
   {
     let mut mock_executor = MockCommandExecutorInterface::new();
     mock_executor.expect_exists()
-      .with(eq("/bin/zsh"), eq("ls"))
-      .returning(|_, _| true);
+      .with(eq("/bin/zsh"), eq("ls"), always())
+      .returning(|_, _, _| false);
 
-    let context = get_test_context();
+    let context = Context::for_test("/home");
 
-    assert!(likely_system_command(&context, &"ls".to_string(), &mock_executor));
+    assert!(!likely_system_command(&context, "alias", &mock_executor, &Classifier::new()));
   }
 
   #[test]
-  fn test_not_likely_system_command () 
+  fn test_parse_intent ()
+  {
+    assert_eq!(parse_intent("!make a backup of this dir"), Intent::Literal("make a backup of this dir".to_string()));
+    assert_eq!(parse_intent("!! grep -q foo bar"), Intent::Literal("! grep -q foo bar".to_string()));
+    assert_eq!(parse_intent("? make a backup of this dir"), Intent::Model("make a backup of this dir".to_string()));
+    assert_eq!(parse_intent("  ls -la "), Intent::Detect("ls -la".to_string()));
+  }
+
+  #[test]
+  fn test_correct_typo ()
+  {
+    let mut mock_executor = MockCommandExecutorInterface::new();
+    mock_executor.expect_exists()
+      .with(eq("/bin/zsh"), eq("lll"), always())
+      .returning(|_, _, _| false);
+
+    let mut context = Context::for_test("/home");
+    context.aliases.follow("alias ll='ls -l'");
+
+    assert_eq!(correct_typo(&context, "lll /tmp", &mock_executor, &Classifier::new(), &CompletionCache::default()), Some("ll /tmp".to_string()));
+  }
+
+  #[test]
+  fn test_alias_likely_system_command () 
+  {
+    let mock_executor = MockCommandExecutorInterface::new();
+    let mut context = Context::for_test("/home");
+    context.aliases.follow("alias ll='ls -l'");
+
+    assert!(likely_system_command(&context, "ll /tmp", &mock_executor, &Classifier::new()));
+  }
+
+  #[test]
+  fn test_suspicious_empty_output ()
+  {
+    let config = Config::parse("fix.empty_output = true");
+    let empty = CommandOutput::from_fields(true, 0, "\n".to_string(), String::new());
+    let found = CommandOutput::from_fields(true, 0, "./notes.md\n".to_string(), String::new());
+
+    assert!(suspicious_empty_output(&config, "find my notes", "find . -name notes", &empty));
+    assert!(suspicious_empty_output(&config, "python files mentioning tokio", "ls *.py | grep tokio", &empty));
+    assert!(!suspicious_empty_output(&config, "find my notes", "find . -name '*notes*'", &found));
+    assert!(!suspicious_empty_output(&config, "remove the build directory", "rm -rf build", &empty));
+    assert!(!suspicious_empty_output(&config, "find . -name notes", "find . -name notes", &empty));
+    assert!(!suspicious_empty_output(&Config::new(), "find my notes", "find . -name notes", &empty));
+  }
+
+  #[test]
+  fn test_line_classifier ()
   {
     let mut mock_executor = MockCommandExecutorInterface::new();
     mock_executor.expect_exists()
-      .with(eq("/bin/zsh"), eq("ls"))
-      .returning(|_, _| false);
+      .with(eq("/bin/zsh"), eq("ls"), always())
+      .times(1)
+      .returning(|_, _, _| true);
 
-    let context = get_test_context();
+    let context = Context::for_test("/home");
+    let classifier = Classifier::new();
+    let line = LineClassifier::new(&context, &mock_executor, &classifier);
 
-    assert!(!likely_system_command(&context, &"alias".to_string(), &mock_executor));
+    // Highlighting and then running the line looks `ls` up once
+    highlight_prompt_input("ls -la", &line);
+    assert!(line.is_command("ls -la"));
+    assert!(line.exists("ls"));
   }
 }
@@ -0,0 +1,82 @@
+/// Incrementally parses a stream of server-sent events, as used by streaming model APIs. Chunks of the response body
+/// are pushed as they arrive, and may split lines (or even UTF-8 characters) anywhere.
+pub struct EventStream {
+  buffer: Vec<u8>,
+  data: Vec<String>,
+}
+
+impl EventStream
+{
+  pub fn new () -> Self
+  {
+    EventStream { buffer: Vec::new(), data: Vec::new() }
+  }
+
+  /// Adds a chunk of the response body, returning the data of each event it completes. An event's `data:` lines are
+  /// joined with newlines; other fields, such as `event:` and `id:`, and comments are ignored.
+  pub fn push (&mut self, chunk: &[u8]) -> Vec<String>
+  {
+    self.buffer.extend_from_slice(chunk);
+    let mut events = Vec::new();
+
+    while let Some(end) = self.buffer.iter().position(|&b| b == b'\n') {
+      let line: Vec<u8> = self.buffer.drain(..=end).collect();
+      let line = String::from_utf8_lossy(&line);
+      let line = line.trim_end_matches(['\n', '\r']);
+
+      if line.is_empty() {
+        // A blank line dispatches the event
+        if !self.data.is_empty() {
+          events.push(self.data.join("\n"));
+          self.data.clear();
+        }
+      } else if let Some(value) = line.strip_prefix("data:") {
+        self.data.push(value.strip_prefix(' ').unwrap_or(value).to_string());
+      }
+    }
+
+    events
+  }
+}
+
+impl Default for EventStream
+{
+  fn default () -> Self
+  {
+    EventStream::new()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_events () {
+    let mut events = EventStream::new();
+    assert_eq!(events.push(b"event: delta\ndata: {\"a\":1}\n\n: keep-alive\n\ndata: [DONE]\n\n"),
+      vec!["{\"a\":1}".to_string(), "[DONE]".to_string()]);
+  }
+
+  #[test]
+  fn test_split_chunks () {
+    let mut events = EventStream::new();
+    assert!(events.push(b"data: ls ").is_empty());
+    assert!(events.push(b"-la\r\n").is_empty());
+    assert_eq!(events.push(b"\r\ndata: x"), vec!["ls -la".to_string()]);
+  }
+
+  #[test]
+  fn test_split_characters () {
+    let mut events = EventStream::new();
+    let text = "data: caf\u{e9}\n\n".as_bytes();
+    assert!(events.push(&text[..9]).is_empty());
+    assert_eq!(events.push(&text[9..]), vec!["caf\u{e9}".to_string()]);
+  }
+
+  #[test]
+  fn test_multiline_data () {
+    let mut events = EventStream::new();
+    assert_eq!(events.push(b"data: a\ndata: b\n\n"), vec!["a\nb".to_string()]);
+  }
+}
@@ -0,0 +1,329 @@
+use std::env;
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+
+use crate::command::CommandExecutorInterface;
+use crate::environment::Environment;
+
+/// Package managers to look for on `$PATH`, in order of preference when several are installed
+static PACKAGE_MANAGERS: &[&str] = &["apt", "dnf", "yum", "pacman", "zypper", "apk", "emerge", "xbps-install",
+  "nix-env", "pkg", "brew", "port"];
+
+/// The flavour of core utilities (`ls`, `sed`, `find` etc.) installed, which determines the flags they accept
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Coreutils {
+  GNU,
+  BSD,
+  BusyBox,
+  #[default]
+  Unknown,
+}
+
+/// Facts about the operating system and environment nl-sh is running in, gathered natively rather than by
+/// asking a model which command to run.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct SystemInfo {
+  /// Operating system name and version, such as "Ubuntu 22.04.4 LTS" or "macOS 14.3.1 (23D60)"
+  pub name: String,
+  pub package_manager: Option<String>,
+  pub init_system: Option<String>,
+  /// The container runtime this shell is running within, if any, such as "docker" or "kubernetes"
+  pub container: Option<String>,
+  pub wsl: bool,
+  pub coreutils: Coreutils,
+}
+
+impl Coreutils
+{
+  /// Classifies the output of `ls --version`. BSD utilities don't accept `--version` at all, so on
+  /// BSD-derived systems the output is an error.
+  pub fn from_version_output (output: &str, bsd_system: bool) -> Self
+  {
+    if output.contains("GNU") || output.contains("uutils") {
+      Coreutils::GNU
+    } else if output.contains("BusyBox") {
+      Coreutils::BusyBox
+    } else if bsd_system {
+      Coreutils::BSD
+    } else {
+      Coreutils::Unknown
+    }
+  }
+
+  pub fn parse (name: &str) -> Self
+  {
+    match name {
+      "GNU" => Coreutils::GNU,
+      "BSD" => Coreutils::BSD,
+      "BusyBox" => Coreutils::BusyBox,
+      _ => Coreutils::Unknown,
+    }
+  }
+
+  pub fn as_str (&self) -> &str
+  {
+    match self {
+      Coreutils::GNU => "GNU",
+      Coreutils::BSD => "BSD",
+      Coreutils::BusyBox => "BusyBox",
+      Coreutils::Unknown => "unknown",
+    }
+  }
+}
+
+impl SystemInfo
+{
+  /// Fingerprints the local system from `/etc/os-release`, `/proc`, `lsb_release` and `sw_vers`, whichever
+  /// are present. Returns `None` if the operating system couldn't be identified.
+  pub fn detect (shell: &str, executor: &dyn CommandExecutorInterface, env: &Environment) -> Option<SystemInfo>
+  {
+    let bsd_system = matches!(env::consts::OS, "macos" | "freebsd" | "openbsd" | "netbsd" | "dragonfly");
+    let proc_version = fs::read_to_string("/proc/version").unwrap_or_default();
+
+    let mut name = fs::read_to_string("/etc/os-release").ok()
+      .and_then(|contents| parse_os_release(&contents));
+
+    if name.is_none() && env::consts::OS == "macos" {
+      name = run(shell, executor, env, "sw_vers").and_then(|output| parse_sw_vers(&output));
+    }
+
+    if name.is_none() && executor.exists(shell, "lsb_release", env) {
+      name = run(shell, executor, env, "lsb_release -ds").map(|output| output.trim().trim_matches('"').to_string());
+    }
+
+    if name.is_none() && !proc_version.is_empty() {
+      name = proc_version.split(" (").next().map(|version| version.trim().to_string());
+    }
+
+    let name = name.filter(|name| !name.is_empty())?;
+    let coreutils = executor.execute(shell, "ls --version", env)
+      .map(|output| Coreutils::from_version_output(&format!("{}{}", output.stdout, output.stderr), bsd_system))
+      .unwrap_or(if bsd_system { Coreutils::BSD } else { Coreutils::Unknown });
+
+    Some(SystemInfo {
+      name,
+      package_manager: PACKAGE_MANAGERS.iter().find(|pm| find_in_path(pm)).map(|pm| pm.to_string()),
+      init_system: detect_init_system(),
+      container: detect_container(),
+      wsl: env::var("WSL_DISTRO_NAME").is_ok() || proc_version.to_lowercase().contains("microsoft"),
+      coreutils,
+    })
+  }
+
+  /// A one-line summary of the system for inclusion in model prompts, such as
+  ///   "Ubuntu 22.04.4 LTS; package manager: apt; init system: systemd; GNU coreutils"
+  pub fn describe (&self) -> String
+  {
+    let mut parts = vec![self.name.clone()];
+
+    if let Some(package_manager) = &self.package_manager {
+      parts.push(format!("package manager: {package_manager}"));
+    }
+
+    if let Some(init_system) = &self.init_system {
+      parts.push(format!("init system: {init_system}"));
+    }
+
+    if self.coreutils != Coreutils::Unknown {
+      parts.push(format!("{} coreutils", self.coreutils.as_str()));
+    }
+
+    if self.wsl {
+      parts.push("running under WSL".to_string());
+    }
+
+    if let Some(container) = &self.container {
+      parts.push(format!("running in a {container} container"));
+    }
+
+    parts.join("; ")
+  }
+}
+
+/// Runs a probe command, returning its stdout if it succeeded
+fn
+run (shell: &str, executor: &dyn CommandExecutorInterface, env: &Environment, command: &str) -> Option<String>
+{
+  executor.execute(shell, command, env)
+    .ok()
+    .filter(|output| output.success)
+    .map(|output| output.stdout)
+}
+
+/// Determines whether an executable named `name` exists in any `$PATH` directory
+pub fn
+find_in_path (name: &str) -> bool
+{
+  env::var_os("PATH")
+    .map(|path| env::split_paths(&path).any(|dir| dir.join(name).is_file()))
+    .unwrap_or(false)
+}
+
+/// Lists the names of the executables in each directory of `path`, a `$PATH`-style list, sorted and without duplicates
+pub fn
+path_executables (path: &str) -> Vec<String>
+{
+  let mut names: Vec<String> = env::split_paths(path)
+    .filter_map(|dir| fs::read_dir(dir).ok())
+    .flat_map(|entries| entries.filter_map(Result::ok))
+    .filter(|entry| fs::metadata(entry.path()).is_ok_and(|metadata| metadata.is_file() && metadata.permissions().mode() & 0o111 != 0))
+    .filter_map(|entry| entry.file_name().into_string().ok())
+    .collect();
+
+  names.sort();
+  names.dedup();
+  names
+}
+
+/// Extracts the distribution name from the contents of `/etc/os-release`, preferring `PRETTY_NAME`
+fn
+parse_os_release (contents: &str) -> Option<String>
+{
+  let field = |key: &str| contents.lines()
+    .find_map(|line| line.strip_prefix(key).and_then(|rest| rest.strip_prefix('=')))
+    .map(|value| value.trim().trim_matches('"').to_string())
+    .filter(|value| !value.is_empty());
+
+  field("PRETTY_NAME").or_else(|| {
+    field("NAME").map(|name| match field("VERSION") {
+      Some(version) => format!("{name} {version}"),
+      None => name
+    })
+  })
+}
+
+/// Builds a name such as "macOS 14.3.1 (23D60)" from the output of `sw_vers`
+fn
+parse_sw_vers (output: &str) -> Option<String>
+{
+  let field = |key: &str| output.lines()
+    .find_map(|line| line.strip_prefix(key))
+    .map(|value| value.trim_start_matches(':').trim().to_string());
+
+  let name = field("ProductName")?;
+  Some(match (field("ProductVersion"), field("BuildVersion")) {
+    (Some(version), Some(build)) => format!("{name} {version} ({build})"),
+    (Some(version), None) => format!("{name} {version}"),
+    _ => name
+  })
+}
+
+fn
+detect_init_system () -> Option<String>
+{
+  if env::consts::OS == "macos" {
+    return Some("launchd".to_string());
+  }
+
+  if Path::new("/run/systemd/system").is_dir() {
+    return Some("systemd".to_string());
+  }
+
+  if Path::new("/run/openrc").is_dir() {
+    return Some("openrc".to_string());
+  }
+
+  // Otherwise go by whatever is running as PID 1
+  fs::read_to_string("/proc/1/comm").ok()
+    .map(|comm| comm.trim().to_string())
+    .filter(|comm| !comm.is_empty())
+}
+
+fn
+detect_container () -> Option<String>
+{
+  if env::var("KUBERNETES_SERVICE_HOST").is_ok() {
+    return Some("kubernetes".to_string());
+  }
+
+  if Path::new("/.dockerenv").exists() {
+    return Some("docker".to_string());
+  }
+
+  if Path::new("/run/.containerenv").exists() {
+    return Some("podman".to_string());
+  }
+
+  // Set by systemd-nspawn, lxc and others
+  if let Ok(container) = env::var("container") {
+    return Some(container);
+  }
+
+  fs::read_to_string("/proc/1/cgroup").ok().and_then(|cgroup| container_from_cgroup(&cgroup))
+}
+
+fn
+container_from_cgroup (cgroup: &str) -> Option<String>
+{
+  [("kubepods", "kubernetes"), ("docker", "docker"), ("lxc", "lxc"), ("containerd", "containerd")]
+    .iter()
+    .find(|(marker, _)| cgroup.contains(marker))
+    .map(|(_, runtime)| runtime.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_parse_os_release () {
+    let ubuntu = "NAME=\"Ubuntu\"\nVERSION=\"22.04.4 LTS (Jammy Jellyfish)\"\nPRETTY_NAME=\"Ubuntu 22.04.4 LTS\"\nID=ubuntu\n";
+    assert_eq!(parse_os_release(ubuntu), Some("Ubuntu 22.04.4 LTS".to_string()));
+
+    let minimal = "NAME=Alpine\nVERSION=3.19\n";
+    assert_eq!(parse_os_release(minimal), Some("Alpine 3.19".to_string()));
+
+    assert_eq!(parse_os_release(""), None);
+  }
+
+  #[test]
+  fn test_parse_sw_vers () {
+    let output = "ProductName:\t\tmacOS\nProductVersion:\t\t14.3.1\nBuildVersion:\t\t23D60\n";
+    assert_eq!(parse_sw_vers(output), Some("macOS 14.3.1 (23D60)".to_string()));
+    assert_eq!(parse_sw_vers("nothing"), None);
+  }
+
+  #[test]
+  fn test_coreutils_from_version_output () {
+    assert_eq!(Coreutils::from_version_output("ls (GNU coreutils) 9.4", false), Coreutils::GNU);
+    assert_eq!(Coreutils::from_version_output("BusyBox v1.36.1 multi-call binary", false), Coreutils::BusyBox);
+    assert_eq!(Coreutils::from_version_output("ls: unrecognized option `--version'", true), Coreutils::BSD);
+    assert_eq!(Coreutils::from_version_output("", false), Coreutils::Unknown);
+  }
+
+  #[test]
+  fn test_container_from_cgroup () {
+    assert_eq!(container_from_cgroup("0::/system.slice/docker-abc123.scope"), Some("docker".to_string()));
+    assert_eq!(container_from_cgroup("0::/kubepods/besteffort/pod1"), Some("kubernetes".to_string()));
+    assert_eq!(container_from_cgroup("0::/init.scope"), None);
+  }
+
+  #[test]
+  fn test_describe () {
+    let system = SystemInfo {
+      name: "Ubuntu 22.04.4 LTS".to_string(),
+      package_manager: Some("apt".to_string()),
+      init_system: Some("systemd".to_string()),
+      container: None,
+      wsl: true,
+      coreutils: Coreutils::GNU,
+    };
+
+    assert_eq!(system.describe(), "Ubuntu 22.04.4 LTS; package manager: apt; init system: systemd; GNU coreutils; running under WSL");
+  }
+
+  #[test]
+  fn test_path_executables () {
+    let first = tempfile::tempdir().unwrap();
+    let second = tempfile::tempdir().unwrap();
+    for (dir, name, mode) in [(&first, "git", 0o755), (&first, "notes.txt", 0o644), (&second, "git", 0o755), (&second, "ls", 0o755)] {
+      let file = dir.path().join(name);
+      fs::write(&file, "").unwrap();
+      fs::set_permissions(&file, fs::Permissions::from_mode(mode)).unwrap();
+    }
+
+    let path = env::join_paths([first.path(), second.path(), Path::new("/nonexistent")]).unwrap();
+    assert_eq!(path_executables(path.to_str().unwrap()), vec!["git".to_string(), "ls".to_string()]);
+  }
+}
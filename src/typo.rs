@@ -0,0 +1,177 @@
+use crate::lexer::split_commands;
+
+/// Rows of a QWERTY keyboard, used to find keys adjacent to one another
+static KEYBOARD_ROWS: &[&str] = &["1234567890-", "qwertyuiop[", "asdfghjkl;'", "zxcvbnm,./"];
+
+/// The cost of inserting or deleting a character, or substituting one for a key which isn't adjacent. Costs are
+/// doubled so that the cheaper typing slips, such as swapped characters, can be given half the cost.
+const EDIT_COST: usize = 2;
+
+/// The cost of the most common slips: swapping two neighbouring characters, hitting an adjacent key, or repeating
+/// (or missing a repeat of) a character
+const SLIP_COST: usize = 1;
+
+/// Corrects the command name at the start of `input` to the closest of `candidates`, such as `gti status` to
+/// `git status`. Returns `None` unless the correction is confident: close enough for the length of the name, and
+/// strictly closer than any other candidate.
+pub fn
+correct_command_line (input: &str, candidates: &[String]) -> Option<String>
+{
+  let commands = split_commands(input);
+  let name = commands.first()?.name()?;
+  if name.quoted || name.text.contains('/') {
+    return None;
+  }
+
+  let correction = correct_word(&name.text, candidates)?;
+  Some(format!("{}{}{}", &input[..name.span.start], correction, &input[name.span.end..]))
+}
+
+/// The candidate confidently closest to `word`, if any
+pub fn
+correct_word (word: &str, candidates: &[String]) -> Option<String>
+{
+  let word = word.to_lowercase();
+  let limit = match word.chars().count() {
+    0..=2 => SLIP_COST,
+    3..=5 => EDIT_COST,
+    _ => EDIT_COST + SLIP_COST
+  };
+
+  let mut best: Option<(usize, &String)> = None;
+  let mut tied = false;
+  for candidate in candidates.iter().filter(|candidate| candidate.len() > 1 && **candidate != word) {
+    let cost = edit_cost(&word, candidate);
+    match best {
+      Some((best_cost, best_candidate)) if cost == best_cost && candidate != best_candidate => tied = true,
+      Some((best_cost, _)) if cost >= best_cost => {},
+      _ => {
+        best = Some((cost, candidate));
+        tied = false;
+      }
+    }
+  }
+
+  match best {
+    Some((cost, candidate)) if cost <= limit && !tied => Some(candidate.clone()),
+    _ => None
+  }
+}
+
+/// A weighted Damerau-Levenshtein distance between `typed` and `intended`, in which typing slips cost half as much
+/// as other edits
+fn
+edit_cost (typed: &str, intended: &str) -> usize
+{
+  let a: Vec<char> = typed.chars().collect();
+  let b: Vec<char> = intended.chars().collect();
+
+  // costs[i][j] is the cost of turning the first i characters of `a` into the first j of `b`
+  let mut costs = vec![vec![0; b.len() + 1]; a.len() + 1];
+  for i in 1..=a.len() {
+    costs[i][0] = costs[i - 1][0] + indel_cost(&a, i - 1);
+  }
+  for j in 1..=b.len() {
+    costs[0][j] = costs[0][j - 1] + indel_cost(&b, j - 1);
+  }
+
+  for i in 1..=a.len() {
+    for j in 1..=b.len() {
+      let substitution = if a[i - 1] == b[j - 1] {
+        0
+      } else if adjacent_keys(a[i - 1], b[j - 1]) {
+        SLIP_COST
+      } else {
+        EDIT_COST
+      };
+
+      let mut cost = (costs[i - 1][j - 1] + substitution)
+        .min(costs[i - 1][j] + indel_cost(&a, i - 1))
+        .min(costs[i][j - 1] + indel_cost(&b, j - 1));
+
+      if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+        cost = cost.min(costs[i - 2][j - 2] + SLIP_COST);
+      }
+
+      costs[i][j] = cost;
+    }
+  }
+
+  costs[a.len()][b.len()]
+}
+
+/// The cost of inserting or deleting the character at `index`; a slip when it repeats the character before it
+fn
+indel_cost (chars: &[char], index: usize) -> usize
+{
+  if index > 0 && chars[index - 1] == chars[index] { SLIP_COST } else { EDIT_COST }
+}
+
+/// Whether two keys neighbour each other on a QWERTY keyboard, including diagonally. Each row is offset half a key
+/// to the right of the one above, so a key touches the keys at the same and next column in the row above, and the
+/// same and previous column in the row below.
+fn
+adjacent_keys (a: char, b: char) -> bool
+{
+  let position = |c: char| KEYBOARD_ROWS.iter().enumerate()
+    .find_map(|(row, keys)| keys.chars().position(|key| key == c.to_ascii_lowercase()).map(|column| (row, column)));
+
+  match (position(a), position(b)) {
+    (Some((row_a, col_a)), Some((row_b, col_b))) => {
+      let (row_a, col_a, row_b, col_b) = (row_a as isize, col_a as isize, row_b as isize, col_b as isize);
+      match row_b - row_a {
+        0 => (col_a - col_b).abs() == 1,
+        -1 => col_b == col_a || col_b == col_a + 1,
+        1 => col_b == col_a || col_b == col_a - 1,
+        _ => false
+      }
+    },
+    _ => false
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn candidates () -> Vec<String>
+  {
+    ["git", "ls", "grep", "make", "python", "python3", "cargo", "docker", "sh", "su", "cat", "cut"]
+      .iter().map(|name| name.to_string()).collect()
+  }
+
+  #[test]
+  fn test_adjacent_keys () {
+    assert!(adjacent_keys('s', 'd'));
+    assert!(adjacent_keys('s', 'w'));
+    assert!(adjacent_keys('s', 'z'));
+    assert!(!adjacent_keys('s', 'l'));
+    assert!(!adjacent_keys('q', 'z'));
+  }
+
+  #[test]
+  fn test_edit_cost () {
+    assert_eq!(edit_cost("gti", "git"), SLIP_COST);
+    assert_eq!(edit_cost("grwp", "grep"), SLIP_COST);
+    assert_eq!(edit_cost("carggo", "cargo"), SLIP_COST);
+    assert_eq!(edit_cost("dcker", "docker"), EDIT_COST);
+    assert_eq!(edit_cost("gti", "gti"), 0);
+  }
+
+  #[test]
+  fn test_correct_command_line () {
+    assert_eq!(correct_command_line("gti status", &candidates()), Some("git status".to_string()));
+    assert_eq!(correct_command_line("sl -la", &candidates()), Some("ls -la".to_string()));
+    assert_eq!(correct_command_line("FOO=1 mkae install", &candidates()), Some("FOO=1 make install".to_string()));
+    assert_eq!(correct_command_line("pyhton script.py", &candidates()), Some("python script.py".to_string()));
+    assert_eq!(correct_command_line("dcoker ps | grep web", &candidates()), Some("docker ps | grep web".to_string()));
+  }
+
+  #[test]
+  fn test_no_confident_correction () {
+    // Too far from anything, or equally close to `cat` and `cut`
+    assert_eq!(correct_command_line("show me my files", &candidates()), None);
+    assert_eq!(correct_command_line("cxt file.txt", &candidates()), None);
+    assert_eq!(correct_command_line("./gti status", &candidates()), None);
+  }
+}
@@ -0,0 +1,348 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::config::Config;
+use crate::context::nl_sh_dir;
+
+/// Prices of the models nl-sh uses by default, in US dollars per million input and output tokens. These can be
+/// overridden, or others added, with settings such as `usage.price.gpt-4 = 30, 60`.
+static DEFAULT_PRICES: &[(&str, f64, f64)] = &[
+  ("gpt-4", 30.0, 60.0),
+  ("gpt-3.5-turbo", 0.5, 1.5),
+  ("claude-3-haiku-20240307", 0.25, 1.25),
+  ("claude-3-sonnet-20240229", 3.0, 15.0),
+  ("claude-3-opus-20240229", 15.0, 75.0),
+];
+
+/// The tokens consumed by a single model request, as reported by the API
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct TokenUsage {
+  pub input: u64,
+  pub output: u64,
+}
+
+/// Accumulated usage of a single model
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ModelUsage {
+  pub model: String,
+  pub calls: u64,
+  pub input_tokens: u64,
+  pub output_tokens: u64,
+  pub cost: f64,
+}
+
+/// Records the tokens consumed by each remote model request, and what they cost. Totals are kept for the session,
+/// and persisted per day under `~/.nl-sh/usage/`, so that an optional daily budget (`usage.budget`, in US dollars)
+/// can block further requests to priced models once it's spent.
+pub struct UsageLedger {
+  prices: HashMap<String, (f64, f64)>,
+  budget: Option<f64>,
+  dir: Option<PathBuf>,
+  session: Vec<ModelUsage>,
+}
+
+impl UsageLedger
+{
+  pub fn new (prices: HashMap<String, (f64, f64)>, budget: Option<f64>, dir: Option<PathBuf>) -> Self
+  {
+    UsageLedger { prices, budget, dir, session: Vec::new() }
+  }
+
+  /// Builds a ledger from the default price table, amended by any `usage.price.<model>` settings
+  pub fn from_config (config: &Config) -> Self
+  {
+    let mut prices: HashMap<String, (f64, f64)> = DEFAULT_PRICES.iter()
+      .map(|(model, input, output)| (model.to_string(), (*input, *output)))
+      .collect();
+
+    for (key, value) in config.entries() {
+      if let Some(model) = key.strip_prefix("usage.price.") {
+        match parse_price(value) {
+          Some(price) => { prices.insert(model.to_string(), price); },
+          None => eprintln!("ignoring invalid {key} \"{value}\"; expected input and output prices such as \"3, 15\"")
+        }
+      }
+    }
+
+    let budget = config.get("usage.budget").and_then(|budget| budget.trim_start_matches('$').parse().ok());
+    UsageLedger::new(prices, budget, nl_sh_dir().map(|dir| dir.join("usage")))
+  }
+
+  /// Takes up changed prices and budget, as from `:config`, keeping the session's totals
+  pub fn reconfigure (&mut self, config: &Config)
+  {
+    let session = std::mem::take(&mut self.session);
+    *self = UsageLedger::from_config(config);
+    self.session = session;
+  }
+
+  /// The cost of a request to `model` in US dollars; unpriced models, such as local ones, are free
+  pub fn cost (&self, model: &str, usage: TokenUsage) -> f64
+  {
+    self.prices.get(model)
+      .map(|(input, output)| (usage.input as f64 * input + usage.output as f64 * output) / 1_000_000.0)
+      .unwrap_or(0.0)
+  }
+
+  /// Refuses requests to priced models once today's spending has reached the budget
+  pub fn check_budget (&self, model: &str) -> Result<(), Box<dyn std::error::Error>>
+  {
+    match self.budget {
+      Some(budget) if self.prices.contains_key(model) => {
+        let spent = total(&self.today()).cost;
+        if spent >= budget {
+          Err(format!("daily usage budget of ${budget:.2} reached (${spent:.2} spent); raise usage.budget to continue").into())
+        } else {
+          Ok(())
+        }
+      },
+      _ => Ok(())
+    }
+  }
+
+  /// Adds a request to the session and daily totals
+  pub fn record (&mut self, model: &str, usage: TokenUsage)
+  {
+    let cost = self.cost(model, usage);
+    add(&mut self.session, model, usage, cost);
+
+    // Re-read the day's totals before adding to them, in case other sessions have recorded usage meanwhile
+    let mut today = self.today();
+    add(&mut today, model, usage, cost);
+    if let Err(e) = self.save(&today) {
+      eprintln!("failed to save usage totals: {e}");
+    }
+  }
+
+  pub fn session (&self) -> &[ModelUsage]
+  {
+    &self.session
+  }
+
+  /// The cost of this session's requests, in US dollars
+  pub fn session_cost (&self) -> f64
+  {
+    self.session.iter().map(|usage| usage.cost).sum()
+  }
+
+  /// Today's totals, across all sessions
+  pub fn today (&self) -> Vec<ModelUsage>
+  {
+    self.day_path()
+      .and_then(|path| fs::read_to_string(path).ok())
+      .map(|contents| parse_day(&contents))
+      .unwrap_or_default()
+  }
+
+  /// Describes the session and daily totals, for the `:usage` builtin
+  pub fn summary (&self) -> String
+  {
+    let today = self.today();
+    let mut lines = vec![format!("session: {}", describe(&total(&self.session)))];
+    lines.extend(self.session.iter().map(|usage| format!("  {}: {}", usage.model, describe(usage))));
+
+    let budget = self.budget.map(|budget| format!(" of ${budget:.2} budget")).unwrap_or_default();
+    lines.push(format!("today: {}{budget}", describe(&total(&today))));
+    lines.extend(today.iter().map(|usage| format!("  {}: {}", usage.model, describe(usage))));
+
+    lines.join("\n")
+  }
+
+  fn day_path (&self) -> Option<PathBuf>
+  {
+    self.dir.as_ref().map(|dir| dir.join(today_date()))
+  }
+
+  fn save (&self, today: &[ModelUsage]) -> io::Result<()>
+  {
+    let path = self.day_path().ok_or_else(|| io::Error::other("could not determine home directory"))?;
+    if let Some(dir) = path.parent() {
+      fs::create_dir_all(dir)?;
+    }
+
+    fs::write(path, serialize_day(today))
+  }
+}
+
+/// Parses a price setting such as `3, 15` into input and output prices
+fn
+parse_price (value: &str) -> Option<(f64, f64)>
+{
+  let (input, output) = value.split_once(',')?;
+  Some((input.trim().parse().ok()?, output.trim().parse().ok()?))
+}
+
+fn
+add (totals: &mut Vec<ModelUsage>, model: &str, usage: TokenUsage, cost: f64)
+{
+  let index = match totals.iter().position(|entry| entry.model == model) {
+    Some(index) => index,
+    None => {
+      totals.push(ModelUsage { model: model.to_string(), ..Default::default() });
+      totals.len() - 1
+    }
+  };
+
+  let entry = &mut totals[index];
+  entry.calls += 1;
+  entry.input_tokens += usage.input;
+  entry.output_tokens += usage.output;
+  entry.cost += cost;
+}
+
+fn
+total (totals: &[ModelUsage]) -> ModelUsage
+{
+  totals.iter().fold(ModelUsage::default(), |mut sum, usage| {
+    sum.calls += usage.calls;
+    sum.input_tokens += usage.input_tokens;
+    sum.output_tokens += usage.output_tokens;
+    sum.cost += usage.cost;
+    sum
+  })
+}
+
+fn
+describe (usage: &ModelUsage) -> String
+{
+  format!("{} calls, {} input and {} output tokens, ${:.4}", usage.calls, usage.input_tokens, usage.output_tokens, usage.cost)
+}
+
+/// Parses a day's totals, stored one model per line as `<model> <calls> <input tokens> <output tokens> <cost>`
+fn
+parse_day (contents: &str) -> Vec<ModelUsage>
+{
+  contents.lines()
+    .filter_map(|line| {
+      let fields: Vec<&str> = line.split_whitespace().collect();
+      match fields.as_slice() {
+        [model, calls, input, output, cost] => Some(ModelUsage {
+          model: model.to_string(),
+          calls: calls.parse().ok()?,
+          input_tokens: input.parse().ok()?,
+          output_tokens: output.parse().ok()?,
+          cost: cost.parse().ok()?,
+        }),
+        _ => None
+      }
+    })
+    .collect()
+}
+
+fn
+serialize_day (totals: &[ModelUsage]) -> String
+{
+  totals.iter()
+    .map(|usage| format!("{} {} {} {} {}\n", usage.model, usage.calls, usage.input_tokens, usage.output_tokens, usage.cost))
+    .collect()
+}
+
+/// Today's date in UTC, as YYYY-MM-DD
+fn
+today_date () -> String
+{
+  let secs = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+  civil_date(secs / 86_400)
+}
+
+/// Converts a count of days since 1970-01-01 into a YYYY-MM-DD date in the proleptic Gregorian calendar
+fn
+civil_date (days: u64) -> String
+{
+  // See Howard Hinnant's "chrono-Compatible Low-Level Date Algorithms"
+  let z = days as i64 + 719_468;
+  let era = z.div_euclid(146_097);
+  let doe = z.rem_euclid(146_097);
+  let yoe = (doe - doe / 1_460 + doe / 36_524 - doe / 146_096) / 365;
+  let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+  let mp = (5 * doy + 2) / 153;
+  let day = doy - (153 * mp + 2) / 5 + 1;
+  let month = if mp < 10 { mp + 3 } else { mp - 9 };
+  let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+
+  format!("{year:04}-{month:02}-{day:02}")
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn ledger (dir: &std::path::Path, budget: Option<f64>) -> UsageLedger
+  {
+    let prices = HashMap::from([("gpt-4".to_string(), (30.0, 60.0))]);
+    UsageLedger::new(prices, budget, Some(dir.to_path_buf()))
+  }
+
+  #[test]
+  fn test_cost () {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let ledger = ledger(temp_dir.path(), None);
+    assert_eq!(ledger.cost("gpt-4", TokenUsage { input: 1000, output: 500 }), 0.06);
+    assert_eq!(ledger.cost("mistral", TokenUsage { input: 1000, output: 500 }), 0.0);
+  }
+
+  #[test]
+  fn test_record () {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let mut ledger = ledger(temp_dir.path(), None);
+    ledger.record("gpt-4", TokenUsage { input: 1000, output: 500 });
+    ledger.record("gpt-4", TokenUsage { input: 1000, output: 500 });
+
+    assert_eq!(ledger.session().len(), 1);
+    assert_eq!(ledger.session()[0].calls, 2);
+    assert_eq!(ledger.session()[0].input_tokens, 2000);
+
+    // Totals persist across sessions
+    let mut other = self::ledger(temp_dir.path(), None);
+    other.record("gpt-4", TokenUsage { input: 1000, output: 500 });
+    assert_eq!(other.session()[0].calls, 1);
+    assert_eq!(other.today()[0].calls, 3);
+  }
+
+  #[test]
+  fn test_budget () {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let mut ledger = ledger(temp_dir.path(), Some(0.10));
+    assert!(ledger.check_budget("gpt-4").is_ok());
+
+    ledger.record("gpt-4", TokenUsage { input: 2000, output: 1000 });
+    assert!(ledger.check_budget("gpt-4").is_err());
+
+    // Unpriced models aren't subject to the budget
+    assert!(ledger.check_budget("mistral").is_ok());
+  }
+
+  #[test]
+  fn test_from_config () {
+    let config = Config::parse("usage.price.gpt-4 = 10, 20\nusage.price.local = bogus\nusage.budget = $2.50");
+    let ledger = UsageLedger::from_config(&config);
+    assert_eq!(ledger.prices.get("gpt-4"), Some(&(10.0, 20.0)));
+    assert_eq!(ledger.prices.get("local"), None);
+    assert_eq!(ledger.budget, Some(2.5));
+  }
+
+  #[test]
+  fn test_reconfigure () {
+    let mut ledger = UsageLedger::new(HashMap::new(), None, None);
+    ledger.record("gpt-4", TokenUsage { input: 1000, output: 500 });
+    ledger.reconfigure(&Config::parse("usage.budget = 5"));
+    assert_eq!(ledger.budget, Some(5.0));
+    assert_eq!(ledger.session.len(), 1);
+  }
+
+  #[test]
+  fn test_parse_day () {
+    let totals = vec![ModelUsage { model: "gpt-4".to_string(), calls: 2, input_tokens: 10, output_tokens: 5, cost: 0.25 }];
+    assert_eq!(parse_day(&serialize_day(&totals)), totals);
+  }
+
+  #[test]
+  fn test_civil_date () {
+    assert_eq!(civil_date(0), "1970-01-01");
+    assert_eq!(civil_date(19_782), "2024-02-29");
+    assert_eq!(civil_date(20_744), "2026-10-18");
+  }
+}